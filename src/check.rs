@@ -0,0 +1,56 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+/// The extensions of the files which --check runs
+const EXTENSIONS: &[&str] = &["calc", "txt", "md"];
+
+/// The scripts (.calc and .txt) and Markdown documents in a directory and its subdirectories, sorted by path. A file is returned as it is.
+pub fn scripts(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut out = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            out.extend(scripts(&path)?);
+        } else if path.extension().is_some_and(|e| EXTENSIONS.iter().any(|x| e == *x)) {
+            out.push(path);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// The lines which differ between two texts, as `line: - old` and `line: + new`. Both are numbered by the lines of the old text, with new lines numbered by the line they follow.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // The lengths of the longest common subsequences of the ends of the texts
+    let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && common[i + 1][j] >= common[i][j + 1]) {
+            out.push(format!("{}: - {}", i + 1, a[i]));
+            i += 1;
+        } else {
+            out.push(format!("{}: + {}", i.max(1), b[j]));
+            j += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,411 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    defaults::{Function, CONSTANTS, DESCRIPTIONS, FUNCTIONS, NUMBERS, SCALES, UNITS},
+    dimensions::Dimensions,
+    error::{CalcError, Result},
+    convert::{Conversion, Scale},
+    convert::Target,
+    expr::{Assertion, Expr, PrintItem, Statement, Tolerance},
+    format::Format,
+    number::Number,
+    unit::Unit,
+    parse::parse,
+    plot::Chart,
+    symbolic,
+    table::Table,
+};
+
+/// Part of the output of a print statement
+#[derive(Clone, Debug, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Value(Number),
+}
+
+/// The result of executing a line
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The value of an expression or assignment
+    Value(Number),
+    /// The output of a print statement
+    Print(Vec<Piece>),
+    /// A description of an assertion which held
+    Passed(String),
+    /// The values of an expression over a range
+    Table(Table),
+    /// An expression, such as a symbolic derivative
+    Expr(Expr),
+    /// A combination of quantities with a dimension, and the dimensionless groups of them
+    Dimensions(Dimensions),
+    /// Curves to draw
+    Plot(Chart),
+    /// A value in the units or on the scale chosen with in
+    Conversion(Conversion),
+}
+
+/// The error for an expression which has no value because it refers to names without values
+fn unknown(expr: &Expr) -> CalcError {
+    CalcError::UnknownName(symbolic::free_names(expr).into_iter().next().unwrap_or_default())
+}
+
+impl Outcome {
+    /// The text printed for this outcome
+    pub fn format(&self, format: &Format) -> String {
+        match self {
+            Outcome::Value(number) => number.format(format),
+            Outcome::Print(pieces) => pieces.iter()
+                .map(|piece| match piece {
+                    Piece::Text(text) => text.clone(),
+                    Piece::Value(number) => number.format(format),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            Outcome::Passed(text) => text.clone(),
+            Outcome::Table(table) => table.format(format),
+            Outcome::Expr(expr) => expr.format(format),
+            Outcome::Dimensions(dimensions) => dimensions.format(format),
+            Outcome::Plot(chart) => chart.render(format, false),
+            Outcome::Conversion(conversion) => conversion.format(format),
+        }
+    }
+}
+
+/// Everything a line can refer to: the units, constants and functions, and the variables defined so far
+#[derive(Clone, Debug)]
+pub struct Context {
+    numbers: HashMap<String, f64>,
+    constants: HashMap<String, Number>,
+    functions: HashMap<String, Function>,
+    units: HashMap<String, Number>,
+    scales: HashMap<String, Scale>,
+    variables: HashMap<String, Number>,
+    /// Variables defined in terms of names which have no value yet
+    symbols: HashMap<String, Expr>,
+    /// Whether a name without a value is an error unless it has been declared, rather than staying symbolic
+    strict: bool,
+    /// The names declared with [`Context::declare`], which may stay symbolic even when strict
+    declared: BTreeSet<String>,
+    /// The text of every assignment, in the order they were made
+    definitions: Vec<String>,
+    /// One-line descriptions of names, for help
+    descriptions: HashMap<String, String>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    /// A context containing the default units, constants and functions and no variables
+    pub fn new() -> Self {
+        Self {
+            numbers: NUMBERS.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            constants: CONSTANTS.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+            functions: FUNCTIONS.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            units: UNITS.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+            scales: SCALES.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            variables: HashMap::new(),
+            symbols: HashMap::new(),
+            strict: false,
+            declared: BTreeSet::new(),
+            definitions: Vec::new(),
+            descriptions: DESCRIPTIONS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    /// Execute a line. A line of the form `name = expression` also stores the result in the variable `name`. If the expression refers to names without values, the variable is defined in terms of them and takes a value once they have one.
+    pub fn execute(&mut self, text: &str) -> Result<Outcome> {
+        let statement = parse(text)?;
+        let outcome = self.run(&statement)?;
+        if let Statement::Assign(name, _) = &statement {
+            if self.functions.contains_key(name) {
+                return Err(CalcError::Domain(format!("{} is a function and cannot be assigned to", name)));
+            }
+            if self.scales.contains_key(name) {
+                return Err(CalcError::Domain(format!("{} is a logarithmic scale and cannot be assigned to", name)));
+            }
+            match &outcome {
+                Outcome::Value(number) => {
+                    self.symbols.remove(name);
+                    self.variables.insert(name.clone(), number.clone());
+                },
+                Outcome::Expr(expr) => {
+                    self.variables.remove(name);
+                    self.symbols.insert(name.clone(), expr.clone());
+                },
+                _ => {},
+            }
+            self.definitions.push(text.trim().to_owned());
+        }
+        Ok(outcome)
+    }
+
+    /// Execute a line whose result is a number, such as an expression or an assignment
+    pub fn eval(&mut self, text: &str) -> Result<Number> {
+        match self.execute(text)? {
+            Outcome::Value(number) => Ok(number),
+            Outcome::Expr(expr) => Err(unknown(&expr)),
+            _ => Err(CalcError::Domain("Only expressions and assignments have values".to_owned())),
+        }
+    }
+
+    /// Find what executing a line would produce, without changing the context. An assignment is evaluated but not stored.
+    pub fn dry_run(&self, text: &str) -> Result<Outcome> {
+        self.run(&parse(text)?)
+    }
+
+    /// Like dry_run, for showing the result of a line while it is typed. Statements which may take a while are not evaluated at all, and give None: tables, plots, dimensional analyses, scaling relations, and anything with a numeric solve or integral.
+    pub fn preview(&self, text: &str) -> Option<Result<Outcome>> {
+        let statement = match parse(text) {
+            Ok(statement) => statement,
+            Err(e) => return Some(Err(e)),
+        };
+        let exprs: Vec<&Expr> = match &statement {
+            Statement::Expr(e) | Statement::Assign(_, e) | Statement::Assert(Assertion::Dimension(e, _)) => vec![e],
+            Statement::Assert(Assertion::Equal(a, b, tolerance)) => match tolerance {
+                Some(Tolerance::Percent(t) | Tolerance::Absolute(t)) => vec![a, b, t],
+                None => vec![a, b],
+            },
+            Statement::Print(items) => items.iter()
+                .filter_map(|item| match item {
+                    PrintItem::Expr(e) => Some(e),
+                    PrintItem::Text(_) => None,
+                })
+                .collect(),
+            Statement::Convert(convert) => {
+                let mut exprs = vec![&convert.expr];
+                if let Target::Units(units) = &convert.target {
+                    exprs.push(units);
+                }
+                exprs.extend(convert.equivalencies.iter().filter_map(|e| e.at.as_ref()));
+                exprs
+            },
+            Statement::Table(..) | Statement::Plot(_) | Statement::DimSolve(_) | Statement::Scaling(_) => return None,
+        };
+        if exprs.into_iter().any(|e| self.is_slow(e)) {
+            return None;
+        }
+        Some(self.run(&statement))
+    }
+
+    /// Whether evaluating an expression runs a numeric solve or integral, directly or through a stored symbol
+    fn is_slow(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Solve(_) | Expr::Integral(_) => true,
+            Expr::Number(_) => false,
+            Expr::Name(name) => self.variable(name).is_none() && self.symbol(name).is_some_and(|definition| self.is_slow(definition)),
+            Expr::Neg(e) | Expr::Level(e, _) => self.is_slow(e),
+            Expr::Binary(_, a, b) => self.is_slow(a) || self.is_slow(b),
+            Expr::Call(_, args) | Expr::List(args) => args.iter().any(|a| self.is_slow(a)),
+            Expr::Derivative(derivative) => self.is_slow(&derivative.expr) || derivative.at.as_ref().is_some_and(|at| self.is_slow(at)),
+        }
+    }
+
+    /// Evaluate a line without changing the context. An assignment is evaluated but not stored.
+    pub fn evaluate(&self, text: &str) -> Result<Number> {
+        match self.dry_run(text)? {
+            Outcome::Value(number) => Ok(number),
+            Outcome::Expr(expr) => Err(unknown(&expr)),
+            _ => Err(CalcError::Domain("Only expressions and assignments have values".to_owned())),
+        }
+    }
+
+    fn run(&self, statement: &Statement) -> Result<Outcome> {
+        match statement {
+            // A symbolic derivative on its own is shown as an expression rather than evaluated
+            Statement::Expr(Expr::Derivative(derivative)) if derivative.at.is_none() => {
+                let expr = symbolic::expand(&derivative.expr, self);
+                self.check_declared(&expr, Some(&derivative.variable))?;
+                Ok(Outcome::Expr(symbolic::derivative(&expr, &derivative.variable)?))
+            },
+            Statement::Expr(e) | Statement::Assign(_, e) => match e.eval(self) {
+                // Names without values stay as symbols
+                Err(CalcError::UnknownName(_)) => {
+                    let expr = symbolic::simplify(&symbolic::partial(e, self)?)?;
+                    self.check_declared(&expr, None)?;
+                    if let Statement::Assign(name, _) = statement && symbolic::free_names(&expr).contains(name) {
+                        return Err(CalcError::Domain(format!("{} cannot be defined in terms of itself", name)));
+                    }
+                    Ok(match expr {
+                        Expr::Number(n) => Outcome::Value(n),
+                        expr => Outcome::Expr(expr),
+                    })
+                },
+                value => Ok(Outcome::Value(value?)),
+            },
+            Statement::Print(items) => {
+                let mut pieces = Vec::new();
+                for item in items {
+                    pieces.push(match item {
+                        PrintItem::Text(text) => Piece::Text(text.clone()),
+                        PrintItem::Expr(e) => Piece::Value(e.eval(self)?),
+                    });
+                }
+                Ok(Outcome::Print(pieces))
+            },
+            Statement::Assert(assertion) => Ok(Outcome::Passed(assertion.check(self)?)),
+            Statement::Table(expr, label, sweep) => Ok(Outcome::Table(sweep.tabulate(self, expr, label)?)),
+            Statement::Scaling(scaling) => Ok(Outcome::Expr(scaling.eval(self)?)),
+            Statement::DimSolve(dimsolve) => Ok(Outcome::Dimensions(dimsolve.eval(self)?)),
+            Statement::Plot(plot) => Ok(Outcome::Plot(plot.eval(self)?)),
+            Statement::Convert(convert) => Ok(Outcome::Conversion(convert.eval(self)?)),
+        }
+    }
+
+    /// When strict, make every name in expr without a value an error unless it was declared, other than the bound variable of a derivative
+    fn check_declared(&self, expr: &Expr, bound: Option<&str>) -> Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+        let undeclared = symbolic::free_names(expr).into_iter().find(|name| {
+            Some(name.as_str()) != bound
+                && !self.declared.contains(name)
+                && !self.symbols.contains_key(name)
+                && self.lookup(name).is_none()
+        });
+        match undeclared {
+            Some(name) => Err(CalcError::UnknownName(name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Require names without values to be declared before they can stay symbolic, so that a misspelled name in a script is an error
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Let a name without a value stay symbolic even when strict, as M does in 2 GN M / c^2
+    pub fn declare(&mut self, name: &str) -> Result<()> {
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(CalcError::Parse(format!("{} is not a name", name)));
+        }
+        self.declared.insert(name.to_owned());
+        Ok(())
+    }
+
+    /// The names declared with [`Context::declare`], in alphabetical order
+    pub fn declared(&self) -> impl Iterator<Item = &str> {
+        self.declared.iter().map(String::as_str)
+    }
+
+    /// Look up the value of a name which is not a function. Variables take precedence over units, which take precedence over constants.
+    pub fn lookup(&self, name: &str) -> Option<Number> {
+        self.variable(name)
+            .or_else(|| self.unit(name))
+            .or_else(|| self.constant(name))
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: Number) {
+        self.variables.insert(name.to_owned(), value);
+    }
+
+    pub fn add_unit(&mut self, name: &str, value: Number) {
+        self.units.insert(name.to_owned(), value);
+    }
+
+    pub fn add_constant(&mut self, name: &str, value: Number) {
+        self.constants.insert(name.to_owned(), value);
+    }
+
+    /// Add a function of one number. Its result has the unit of its argument raised to unit_mult, and a unit_mult of zero requires a unitless argument.
+    pub fn add_function(&mut self, name: &str, f: fn(f64) -> f64, unit_mult: f64) {
+        self.functions.insert(name.to_owned(), Function::Map { f, unit_mult });
+    }
+
+    /// Set the description help shows for a name
+    pub fn set_description(&mut self, name: &str, description: &str) {
+        self.descriptions.insert(name.to_owned(), description.to_owned());
+    }
+
+    pub fn description(&self, name: &str) -> Option<&str> {
+        self.descriptions.get(name).map(|d| d.as_str())
+    }
+
+    pub fn variable(&self, name: &str) -> Option<Number> {
+        self.variables.get(name).cloned()
+    }
+
+    /// The definition of a variable which is defined in terms of names without values
+    pub fn symbol(&self, name: &str) -> Option<&Expr> {
+        self.symbols.get(name)
+    }
+
+    pub fn unit(&self, name: &str) -> Option<Number> {
+        self.units.get(name).cloned()
+    }
+
+    /// Look up a logarithmic scale, such as ABmag
+    pub fn scale(&self, name: &str) -> Option<Scale> {
+        self.scales.get(name).copied()
+    }
+
+    /// Look up a constant, including unitless numbers such as pi
+    pub fn constant(&self, name: &str) -> Option<Number> {
+        if let Some(q) = self.numbers.get(name) {
+            return Some(Number::new(*q, Unit::one()));
+        }
+        self.constants.get(name).cloned()
+    }
+
+    pub fn function(&self, name: &str) -> Option<Function> {
+        self.functions.get(name).cloned()
+    }
+
+    /// The text of every assignment made with [`Context::eval`], in order. Evaluating them in a new context restores the variables.
+    pub fn definitions(&self) -> &[String] {
+        &self.definitions
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &Number)> {
+        self.variables.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// The variables defined in terms of names without values, with their definitions
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, &Expr)> {
+        self.symbols.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = (&str, &Number)> {
+        self.units.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn scales(&self) -> impl Iterator<Item = (&str, &Scale)> {
+        self.scales.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn constants(&self) -> impl Iterator<Item = (&str, &Number)> {
+        self.constants.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn numbers(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.numbers.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (&str, &Function)> {
+        self.functions.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_contexts_need_declared_symbols() {
+        let mut ctx = Context::new();
+        ctx.set_strict(true);
+        assert_eq!(ctx.execute("r = 2 GN msn / c^2").unwrap_err(), CalcError::UnknownName("msn".to_owned()));
+        assert_eq!(ctx.execute("diff(x^2 + tpyo x, x)").unwrap_err(), CalcError::UnknownName("tpyo".to_owned()));
+        // The variable of a derivative needs no declaration
+        assert!(matches!(ctx.execute("diff(x^2, x)"), Ok(Outcome::Expr(_))));
+        ctx.declare("M").unwrap();
+        assert!(matches!(ctx.execute("r = 2 GN M / c^2"), Ok(Outcome::Expr(_))));
+        assert!(matches!(ctx.execute("r / 2"), Ok(Outcome::Expr(_))));
+        ctx.execute("M = 1 msun").unwrap();
+        assert!(matches!(ctx.execute("r"), Ok(Outcome::Value(_))));
+        assert!(ctx.declare("2x").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{context::Context, defaults::{CONSTANTS, UNITS}, error::{CalcError, Result}, expr::Expr, format::Format, number::{Magnitude, Number}, unit::Unit};
+
+/// A logarithmic scale, on which a quantity x has the level factor · log10(x / reference).
+///
+/// A level such as 20 ABmag stays on its scale: adding a plain number shifts it, and the difference of two levels on the same scale is a plain number. Other arithmetic needs the quantity it measures, which in gives, as in 20 ABmag in Jy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scale {
+    pub factor: f64,
+    /// The quantity at level zero, in CGS units
+    pub reference: f64,
+    pub unit: Unit,
+}
+
+/// What a unit measures, for errors
+fn measures(u: Unit) -> String {
+    match u.dimension_name() {
+        Some("dimensionless") => "plain numbers".to_owned(),
+        Some(dimension) => format!("a {}", dimension),
+        None => format!("quantities in{}", u.format(&Format::default())),
+    }
+}
+
+impl Scale {
+    /// The quantity at a level, which is a plain number
+    pub fn linear(&self, level: &Magnitude) -> Number {
+        Number::from_magnitude(level.map(|n| self.reference * 10f64.powf(n / self.factor)), self.unit)
+    }
+
+    /// The level of a quantity on the scale called name
+    pub fn level(&self, name: &str, n: &Number) -> Result<Magnitude> {
+        if n.u != self.unit {
+            return Err(CalcError::Unit(format!("Cannot write {} in {}, which measures {}", n, name, measures(self.unit))));
+        }
+        Magnitude::zip_all(std::slice::from_ref(&n.q), |q| match q[0] / self.reference {
+            ratio if ratio > 0. || ratio.is_nan() => Ok(self.factor * ratio.log10()),
+            _ => Err(CalcError::Domain(format!("Only positive quantities have a level in {}, but {} is not", name, Number::new(q[0], self.unit)))),
+        })
+    }
+
+    /// The quantity at level n, for help
+    pub fn rule(&self) -> String {
+        let exponent = match self.factor {
+            1. => "n".to_owned(),
+            f if f < 0. => format!("(-n/{})", -f),
+            f => format!("(n/{})", f),
+        };
+        if self.reference == 1. && self.unit.is_one() {
+            format!("10^{}", exponent)
+        } else {
+            format!("10^{} · {}", exponent, Number::new(self.reference, self.unit))
+        }
+    }
+}
+
+/// A value written in the units or on the scale chosen with `in`, such as 3.631e-5 Jy or 20 ABmag
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversion {
+    pub value: Magnitude,
+    /// The units or scale as written
+    pub unit: String,
+}
+
+impl Conversion {
+    pub fn format(&self, format: &Format) -> String {
+        format!("{} {}", self.value.format(format), self.unit)
+    }
+}
+
+/// The equivalencies `in ... with` accepts: their names, what they relate, and an example
+pub const EQUIVALENCIES: &[(&str, &str, &str)] = &[
+    ("spectral", "wavelength, frequency, wavenumber and photon energy", "5000 angstrom in eV with spectral"),
+    ("thermal", "temperature and energy, through kb", "1 keV in K with thermal"),
+    ("mass_energy", "mass and energy, through c^2", "1 g in erg with mass_energy"),
+    ("parallax", "parallax angle and distance", "1 mas in pc with parallax"),
+    ("spectral_density", "F_nu and F_lambda at a wavelength, frequency or photon energy", "1 Jy in erg / s / cm^2 / angstrom with spectral_density(5000 angstrom)"),
+];
+
+/// One kind of quantity as a power law in another: to = factor · from^power
+#[derive(Clone, Debug, PartialEq)]
+pub struct Relation {
+    from: Unit,
+    factor: Number,
+    power: f64,
+}
+
+impl Relation {
+    fn new(from: [f64; 3], factor: Number, power: f64) -> Self {
+        Self { from: Unit::new(from), factor, power }
+    }
+
+    fn to(&self) -> Unit {
+        self.factor.u + self.from * self.power
+    }
+
+    fn factor(&self) -> f64 {
+        self.factor.value().unwrap_or(f64::NAN)
+    }
+
+    fn forward(&self, q: f64) -> f64 {
+        self.factor() * q.powf(self.power)
+    }
+
+    fn backward(&self, q: f64) -> f64 {
+        (q / self.factor()).powf(1. / self.power)
+    }
+}
+
+fn constant(name: &str) -> Number {
+    CONSTANTS.get(name).or_else(|| UNITS.get(name)).cloned().unwrap_or(Number::new(f64::NAN, Unit::one()))
+}
+
+/// The relations of an equivalency. spectral_density needs the frequency at which flux densities are compared.
+pub fn relations(name: &str, frequency: Option<f64>) -> Result<Vec<Relation>> {
+    let (c, h) = (constant("c"), constant("h"));
+    let plain = |q: f64| Number::new(q, Unit::one());
+    Ok(match (name, frequency) {
+        ("spectral", _) => vec![
+            // frequency = c / wavelength, energy = h frequency, wavenumber = 1 / wavelength
+            Relation::new([1., 0., 0.], c, -1.),
+            Relation::new([0., 0., -1.], h, 1.),
+            Relation::new([1., 0., 0.], plain(1.), -1.),
+        ],
+        // Temperatures are plain numbers, since kb is per kelvin
+        ("thermal", _) => vec![Relation::new([0., 0., 0.], constant("kb"), 1.)],
+        ("mass_energy", _) => vec![Relation::new([0., 1., 0.], c.clone().mul(c)?, 1.)],
+        ("parallax", _) => vec![Relation::new([0., 0., 0.], constant("AU"), -1.)],
+        // F_lambda = F_nu frequency^2 / c
+        ("spectral_density", Some(frequency)) => vec![
+            Relation::new([0., 1., -2.], Number::new(frequency * frequency, Unit::new([0., 0., -2.])).div(c)?, 1.),
+        ],
+        ("spectral_density", None) => {
+            return Err(CalcError::Parse("Give spectral_density the wavelength, frequency or photon energy to compare at, as in spectral_density(5000 angstrom)".to_owned()));
+        },
+        _ => {
+            let names: Vec<&str> = EQUIVALENCIES.iter().map(|(name, _, _)| *name).collect();
+            return Err(CalcError::Parse(format!("Unknown equivalency {}. The equivalencies are {}", name, names.join(", "))));
+        },
+    })
+}
+
+/// n in another unit, through a chain of relations, each of which can be used in either direction. None if no chain reaches the unit.
+pub fn through(relations: &[Relation], n: &Number, unit: Unit) -> Option<Number> {
+    // A breadth-first search, keeping the first way each unit is reached
+    let mut reached = vec![(n.u, None::<(usize, bool, usize)>)];
+    let mut next = 0;
+    while next < reached.len() && !reached.iter().any(|(u, _)| *u == unit) {
+        let here = reached[next].0;
+        // A plain number is a temperature to one equivalency and an angle to another, so a chain can start or end there but not pass through
+        if next > 0 && here.is_one() {
+            next += 1;
+            continue;
+        }
+        for (i, relation) in relations.iter().enumerate() {
+            for (forward, from, to) in [(true, relation.from, relation.to()), (false, relation.to(), relation.from)] {
+                if from == here && !reached.iter().any(|(u, _)| *u == to) {
+                    reached.push((to, Some((i, forward, next))));
+                }
+            }
+        }
+        next += 1;
+    }
+    let mut at = reached.iter().position(|(u, _)| *u == unit)?;
+    let mut steps = Vec::new();
+    while let Some((i, forward, previous)) = reached[at].1 {
+        steps.push((&relations[i], forward));
+        at = previous;
+    }
+    let q = steps.iter().rev().fold(n.q.clone(), |q, (relation, forward)| {
+        q.map(|x| if *forward { relation.forward(x) } else { relation.backward(x) })
+    });
+    Some(Number::from_magnitude(q, unit))
+}
+
+/// The frequency of a wavelength, frequency or photon energy
+pub fn frequency(n: &Number) -> Result<f64> {
+    let spectral = relations("spectral", None)?;
+    match through(&spectral, n, Unit::new([0., 0., -1.])).and_then(|f| f.value()) {
+        Some(frequency) => Ok(frequency),
+        None => Err(CalcError::Unit(format!("{} is not a wavelength, frequency or photon energy", n))),
+    }
+}
+
+/// A hint, for an error about quantities with different units, naming an equivalency which relates them. Plain numbers could be temperatures or angles, so they get no hint.
+pub fn hint(a: Unit, b: Unit) -> String {
+    if a.is_one() || b.is_one() {
+        return String::new();
+    }
+    EQUIVALENCIES.iter()
+        .find(|(name, _, _)| {
+            relations(name, Some(1.)).is_ok_and(|relations| through(&relations, &Number::new(1., a), b).is_some())
+        })
+        .map(|(name, _, example)| format!(". The {} equivalency relates them, as in {}", name, example))
+        .unwrap_or_default()
+}
+
+/// What `in` converts to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// Units written as an expression, such as km / hr
+    Units(Expr),
+    /// A logarithmic scale, such as ABmag
+    Scale(String),
+}
+
+/// `with spectral` or `with spectral_density(5000 angstrom)`: an equivalency which lets `in` cross dimensions
+#[derive(Clone, Debug, PartialEq)]
+pub struct Equivalency {
+    pub name: String,
+    /// The wavelength, frequency or photon energy spectral_density compares at
+    pub at: Option<Expr>,
+}
+
+/// `expr in target with equivalencies`: the value of expr in other units or on a logarithmic scale
+#[derive(Clone, Debug, PartialEq)]
+pub struct Convert {
+    pub expr: Expr,
+    pub target: Target,
+    pub equivalencies: Vec<Equivalency>,
+}
+
+impl Convert {
+    pub fn eval(&self, ctx: &Context) -> Result<Conversion> {
+        let n = self.expr.eval(ctx)?;
+        // A level is only turned into the quantity it measures here
+        let n = match &n.scale {
+            Some(name) if matches!(&self.target, Target::Scale(target) if target == name) => {
+                return Ok(Conversion { value: n.q, unit: name.clone() });
+            },
+            Some(name) => ctx.scale(name).ok_or_else(|| CalcError::UnknownName(name.clone()))?.linear(&n.q),
+            None => n,
+        };
+        match &self.target {
+            Target::Units(units) => {
+                let size = units.eval(ctx)?.quantity("The units to convert to")?;
+                let Some(q) = size.value() else {
+                    return Err(CalcError::Domain("Cannot convert to an array of units".to_owned()));
+                };
+                let n = self.equivalent(n, size.u, &units.to_string(), ctx)?;
+                Ok(Conversion { value: n.q.map(|x| x / q), unit: units.to_string() })
+            },
+            Target::Scale(name) => {
+                let scale = ctx.scale(name).ok_or_else(|| CalcError::UnknownName(name.clone()))?;
+                let n = self.equivalent(n, scale.unit, name, ctx)?;
+                Ok(Conversion { value: scale.level(name, &n)?, unit: name.clone() })
+            },
+        }
+    }
+
+    /// n in the given unit, through the equivalencies if its own unit differs
+    fn equivalent(&self, n: Number, unit: Unit, target: &str, ctx: &Context) -> Result<Number> {
+        let mut known = Vec::new();
+        for equivalency in &self.equivalencies {
+            let at = match &equivalency.at {
+                Some(at) => Some(frequency(&at.eval(ctx)?.quantity("The point of an equivalency")?)?),
+                None => None,
+            };
+            known.extend(relations(&equivalency.name, at)?);
+        }
+        if n.u == unit {
+            return Ok(n);
+        }
+        if self.equivalencies.is_empty() {
+            return Err(CalcError::Unit(format!("Cannot convert {} to {}, which has different units{}", n, target, hint(n.u, unit))));
+        }
+        through(&known, &n, unit).ok_or_else(|| {
+            let names: Vec<&str> = self.equivalencies.iter().map(|e| e.name.as_str()).collect();
+            CalcError::Unit(format!("Cannot convert {} to {}, even with {}{}", n, target, names.join(", "), hint(n.u, unit)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::{Context, Outcome}, error::CalcError, number::Magnitude};
+
+    fn converted(ctx: &mut Context, text: &str) -> (f64, String) {
+        match ctx.execute(text).unwrap() {
+            Outcome::Conversion(conversion) => match conversion.value {
+                Magnitude::Scalar(q) => (q, conversion.unit),
+                Magnitude::Array(_) => panic!("{} gave an array", text),
+            },
+            _ => panic!("{} is not a conversion", text),
+        }
+    }
+
+    #[test]
+    fn levels_shift_and_subtract_on_their_scale() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.eval("20 ABmag + 0.5").unwrap(), ctx.eval("20.5 ABmag").unwrap());
+        assert_eq!(ctx.eval("0.5 + 20 ABmag").unwrap(), ctx.eval("20.5 ABmag").unwrap());
+        assert_eq!(ctx.eval("20 ABmag - 0.5").unwrap(), ctx.eval("19.5 ABmag").unwrap());
+        // The difference of two levels is a plain number
+        assert_eq!(ctx.eval("20 ABmag - 19 ABmag").unwrap(), ctx.eval("1").unwrap());
+        assert_eq!(ctx.eval("20 ABmag - 19 ABmag").unwrap().scale, None);
+    }
+
+    #[test]
+    fn other_arithmetic_on_levels_is_an_error() {
+        let mut ctx = Context::new();
+        for text in ["2 * 20 ABmag", "20 ABmag / 2", "10 dB + 3 dB", "20 ABmag - 3 dex", "20 ABmag + 1 km", "1 - 20 ABmag", "sqrt(20 ABmag)"] {
+            assert!(matches!(ctx.eval(text), Err(CalcError::Unit(_))), "{} should be a unit error", text);
+        }
+    }
+
+    #[test]
+    fn in_converts_levels_to_what_they_measure() {
+        let mut ctx = Context::new();
+        let (q, unit) = converted(&mut ctx, "20 ABmag in Jy");
+        assert!((q - 3.631e-5).abs() < 1e-12, "20 ABmag is {} Jy", q);
+        assert_eq!(unit, "Jy");
+        assert_eq!(converted(&mut ctx, "20 ABmag in ABmag"), (20., "ABmag".to_owned()));
+        let (q, _) = converted(&mut ctx, "3 dex in 1");
+        assert!((q - 1000.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn in_converts_quantities_to_levels() {
+        let mut ctx = Context::new();
+        assert_eq!(converted(&mut ctx, "3631 Jy in ABmag"), (0., "ABmag".to_owned()));
+        assert_eq!(converted(&mut ctx, "1000 in dex"), (3., "dex".to_owned()));
+        assert_eq!(converted(&mut ctx, "100 in dB"), (20., "dB".to_owned()));
+        assert_eq!(converted(&mut ctx, "1 mW in dBm"), (0., "dBm".to_owned()));
+        let (level, _) = converted(&mut ctx, "-30 dBm in mW");
+        assert!((level - 1e-3).abs() < 1e-15);
+        // A level converted to its own scale keeps its number
+        assert_eq!(converted(&mut ctx, "(20 ABmag + 1) in ABmag"), (21., "ABmag".to_owned()));
+        assert!(matches!(ctx.execute("1 km in ABmag"), Err(CalcError::Unit(_))));
+        assert!(ctx.execute("-1 in dex").is_err());
+    }
+
+    fn close(ctx: &mut Context, text: &str, expected: f64, unit: &str) {
+        let (q, written) = converted(ctx, text);
+        assert_eq!(written, unit);
+        assert!((q / expected - 1.).abs() < 1e-6, "{} is {}, not {}", text, q, expected);
+    }
+
+    #[test]
+    fn equivalencies_cross_dimensions() {
+        let mut ctx = Context::new();
+        close(&mut ctx, "5000 angstrom in eV with spectral", 2.47968, "eV");
+        close(&mut ctx, "1 eV in angstrom with spectral", 12398.4, "angstrom");
+        close(&mut ctx, "1 GHz in cm with spectral", 29.9792458, "cm");
+        close(&mut ctx, "1 eV in K with thermal", 11604.11, "K");
+        close(&mut ctx, "1 g in erg with mass_energy", 8.987551787e20, "erg");
+        close(&mut ctx, "1 Jy in erg/s/cm^2/angstrom with spectral_density(5000 angstrom)", 1.199169832e-12, "erg / s / cm^2 / angstrom");
+        // Equivalencies chain through one another
+        close(&mut ctx, "1 keV in angstrom with spectral, thermal", 12.3984037, "angstrom");
+        // Parallax is exact up to the rounding of the AU and pc in the defaults
+        let (distance, _) = converted(&mut ctx, "1 mas in pc with parallax");
+        assert!((distance / 1000. - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn missing_equivalencies_are_suggested() {
+        let mut ctx = Context::new();
+        let Err(CalcError::Unit(message)) = ctx.execute("5000 angstrom in eV") else {
+            panic!("converting a length to an energy should fail");
+        };
+        assert!(message.contains("with spectral"), "{}", message);
+        assert!(ctx.execute("1 Jy in erg/s/cm^2/angstrom with spectral_density").is_err());
+        assert!(matches!(ctx.execute("1 km in eV with thermal"), Err(CalcError::Unit(_))));
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use crate::{convert::Scale, format::Format, number::Number, numeric, unit::Unit};
+
+/// A function of numbers, or of the elements of arrays
+#[derive(Clone, Copy, Debug)]
+pub enum Function {
+    /// Applied to each element of an array
+    Map {
+        f: fn(f64) -> f64,
+        /// The power the argument's unit is raised to. A power of zero requires a unitless argument.
+        unit_mult: f64,
+    },
+    /// Reduces the elements of an array, or a single number, to one number
+    Reduce {
+        f: fn(&[f64]) -> f64,
+        /// Whether the result has the unit of the argument, rather than no unit
+        keeps_unit: bool,
+    },
+    /// Takes unitless arguments, applied to each element of arrays, which must lie in its domain
+    Special {
+        f: fn(&[f64]) -> f64,
+        /// The names of the arguments, for usage and errors
+        args: &'static [&'static str],
+        /// Why the arguments are outside the domain, if they are
+        domain: fn(&[f64]) -> Option<&'static str>,
+    },
+}
+
+impl Function {
+    /// A description of how the function treats units
+    pub fn unit_rule(&self) -> String {
+        match *self {
+            Function::Map { unit_mult: 0., .. } => "unitless argument and result".to_owned(),
+            Function::Map { unit_mult: 1., .. } => "keeps the unit of its argument".to_owned(),
+            Function::Map { unit_mult, .. } => format!("unit^{}", Format::default().apply_exponent(unit_mult)),
+            Function::Reduce { keeps_unit: true, .. } => "reduces an array, keeping its unit".to_owned(),
+            Function::Reduce { keeps_unit: false, .. } => "reduces an array to a unitless number".to_owned(),
+            Function::Special { args: [_], .. } => "unitless argument and result".to_owned(),
+            Function::Special { .. } => "unitless arguments and result".to_owned(),
+        }
+    }
+
+    /// How to call the function, with names for its arguments
+    pub fn usage(&self, name: &str) -> String {
+        match self {
+            Function::Special { args, .. } => format!("{}({})", name, args.join(", ")),
+            _ => format!("{}(x)", name),
+        }
+    }
+}
+
+/// The digamma function, from the reflection formula below 1/2, the recurrence up to 10 and an asymptotic series beyond
+fn digamma(x: f64) -> f64 {
+    if x <= 0. && x.fract() == 0. {
+        return f64::NAN;
+    }
+    if x < 0.5 {
+        return digamma(1. - x) - std::f64::consts::PI / (std::f64::consts::PI * x).tan();
+    }
+    let mut x = x;
+    let mut result = 0.;
+    while x < 10. {
+        result -= 1. / x;
+        x += 1.;
+    }
+    let r = 1. / (x * x);
+    result + x.ln() - 0.5 / x - r * (1. / 12. - r * (1. / 120. - r * (1. / 252. - r * (1. / 240. - r / 132.))))
+}
+
+/// The trigamma function, the derivative of digamma, in the same way
+fn trigamma(x: f64) -> f64 {
+    if x <= 0. && x.fract() == 0. {
+        return f64::NAN;
+    }
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).powi(2) - trigamma(1. - x);
+    }
+    let mut x = x;
+    let mut result = 0.;
+    while x < 10. {
+        result += 1. / (x * x);
+        x += 1.;
+    }
+    let r = 1. / (x * x);
+    result + 1. / x + r / 2. + r / x * (1. / 6. - r * (1. / 30. - r * (1. / 42. - r / 30.)))
+}
+
+/// The largest argument of a Bessel function of real order, well below where the continued fractions puruspe uses stop converging
+const BESSEL_MAX_X: f64 = 5000.;
+/// The largest order of a Bessel function. Above it puruspe's recurrences overflow at small x.
+const BESSEL_MAX_ORDER: f64 = 100.;
+
+/// A Bessel function of order nu, from the faster integer-order function when nu is whole
+fn bessel(nu: f64, x: f64, integer: fn(u32, f64) -> f64, real: fn(f64, f64) -> (f64, f64), second: bool) -> f64 {
+    if nu.fract() == 0. {
+        integer(nu as u32, x)
+    } else {
+        let (first_kind, second_kind) = real(nu, x);
+        if second { second_kind } else { first_kind }
+    }
+}
+
+/// Why a Bessel function cannot be evaluated, if it cannot. Functions of the second kind and of real order need x > 0.
+fn bessel_domain(x: &[f64], second: bool) -> Option<&'static str> {
+    let (nu, x) = (x[0], x[1]);
+    if !(0. ..=BESSEL_MAX_ORDER).contains(&nu) {
+        Some("needs an order 0 <= nu <= 100")
+    } else if (second || nu.fract() != 0.) && x <= 0. {
+        Some("needs x > 0")
+    } else if nu.fract() != 0. && x > BESSEL_MAX_X {
+        Some("of real order cannot be evaluated for x > 5000")
+    } else {
+        None
+    }
+}
+
+/// The smallest a for which puruspe approximates the incomplete gamma functions, which fails below x = a - 1
+const GAMMA_SWITCH: f64 = 100.;
+/// The most terms of the series or continued fraction for the incomplete gamma functions
+const GAMMA_TERMS: usize = 10_000_000;
+/// puruspe approximates the incomplete beta function when a and b are both above this, which fails
+const BETA_SWITCH: f64 = 3000.;
+
+/// The regularized incomplete gamma functions P(a, x) and Q(a, x) = 1 - P(a, x). Large a uses the series for P below x = a + 1 and the continued fraction for Q above, as puruspe does for small a.
+fn incomplete_gamma(a: f64, x: f64) -> (f64, f64) {
+    if a < GAMMA_SWITCH {
+        return (puruspe::gammp(a, x), puruspe::gammq(a, x));
+    }
+    if x == f64::INFINITY {
+        return (1., 0.);
+    }
+    // x^a exp(-x) / gamma(a), with Stirling's series for gamma(a) so that the large terms cancel exactly
+    let stirling = 1. / (12. * a) - 1. / (360. * a.powi(3)) + 1. / (1260. * a.powi(5));
+    let prefactor = (a * ((x - a) / a).ln_1p() - (x - a) + 0.5 * (a / std::f64::consts::TAU).ln() - stirling).exp();
+    if x < a + 1. {
+        let (mut term, mut sum, mut n) = (1. / a, 1. / a, a);
+        for _ in 0..GAMMA_TERMS {
+            n += 1.;
+            term *= x / n;
+            sum += term;
+            if term < sum * f64::EPSILON {
+                break;
+            }
+        }
+        let p = sum * prefactor;
+        (p, 1. - p)
+    } else {
+        // Lentz's method
+        let tiny = f64::MIN_POSITIVE / f64::EPSILON;
+        let mut b = x + 1. - a;
+        let (mut c, mut d) = (1. / tiny, 1. / b);
+        let mut h = d;
+        for i in 1..GAMMA_TERMS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.;
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+            d = 1. / d;
+            h *= d * c;
+            if (d * c - 1.).abs() <= f64::EPSILON {
+                break;
+            }
+        }
+        let q = prefactor * h;
+        (1. - q, q)
+    }
+}
+
+/// The x for which P(a, x) = p. puruspe's Newton steps leave the domain of P for large a, so those are found by bracketing instead.
+fn inverse_incomplete_gamma(p: f64, a: f64) -> f64 {
+    if a < GAMMA_SWITCH || p <= 0. || p >= 1. {
+        return puruspe::invgammp(p, a);
+    }
+    // P is within rounding of 1 this far above the mean
+    let hi = a + 40. * a.sqrt();
+    numeric::solve(|x| Ok(incomplete_gamma(a, x).0 - p), 0., hi).unwrap_or(f64::NAN)
+}
+
+lazy_static! {
+    pub static ref NUMBERS: HashMap<&'static str, f64> = {
+        let mut a = HashMap::new();
+        a.insert("pi", std::f64::consts::PI);
+        a.insert("e", std::f64::consts::E);
+        a.insert("egamma", 0.577_215_664_901_532_9);
+        a.insert("inf", f64::INFINITY);
+        a
+    };
+    pub static ref CONSTANTS: HashMap<&'static str, Number> = {
+        let mut a = HashMap::new();
+        a.insert("electron_mass",  Number::new(9.1093897e-28, Unit::new([0., 1., 0.])));
+        a.insert("proton_mass", Number::new(1.6726231e-24, Unit::new([0., 1., 0.])));
+        a.insert("electron_charge", Number::new(4.8032068e-10, Unit::new([1.5, 0.5, -1.])));
+        a.insert("GN", Number::new(6.6743e-8, Unit::new([3., -1., -2.])));
+        a.insert("h", Number::new((2.*std::f64::consts::PI)*1.05457266e-27, Unit::new([2., 1., -1.])));
+        a.insert("hbar", Number::new(1.05457266e-27, Unit::new([2., 1., -1.])));
+        a.insert("c", Number::new(2.99792458e10, Unit::new([1., 0., -1.])));
+        a.insert("kb", Number::new(1.3807e-16, Unit::new([2., 1., -2.]))); // Also times K^-1
+        a
+    };
+    pub static ref FUNCTIONS: HashMap<&'static str, Function> = {
+        let mut a: HashMap<&'static str, Function> = HashMap::new();
+        a.insert("sqrt", Function::Map { f: |x| x.sqrt(), unit_mult: 0.5 });
+        a.insert("cbrt", Function::Map { f: |x| x.cbrt(), unit_mult: 1./3. });
+        a.insert("exp", Function::Map { f: |x| x.exp(), unit_mult: 0. });
+        a.insert("ln", Function::Map { f: |x| x.ln(), unit_mult: 0. });
+        a.insert("log10", Function::Map { f: |x| x.log10(), unit_mult: 0. });
+        a.insert("log2", Function::Map { f: |x| x.log2(), unit_mult: 0. });
+        a.insert("sin", Function::Map { f: |x| x.sin(), unit_mult: 0. });
+        a.insert("cos", Function::Map { f: |x| x.cos(), unit_mult: 0. });
+        a.insert("tan", Function::Map { f: |x| x.tan(), unit_mult: 0. });
+        a.insert("asin", Function::Map { f: |x| x.asin(), unit_mult: 0. });
+        a.insert("acos", Function::Map { f: |x| x.acos(), unit_mult: 0. });
+        a.insert("atan", Function::Map { f: |x| x.atan(), unit_mult: 0. });
+        a.insert("sinh", Function::Map { f: |x| x.sinh(), unit_mult: 0. });
+        a.insert("cosh", Function::Map { f: |x| x.cosh(), unit_mult: 0. });
+        a.insert("tanh", Function::Map { f: |x| x.tanh(), unit_mult: 0. });
+        a.insert("asinh", Function::Map { f: |x| x.asinh(), unit_mult: 0. });
+        a.insert("acosh", Function::Map { f: |x| x.acosh(), unit_mult: 0. });
+        a.insert("atanh", Function::Map { f: |x| x.atanh(), unit_mult: 0. });
+        a.insert("abs", Function::Map { f: |x| x.abs(), unit_mult: 1. });
+        a.insert("floor", Function::Map { f: |x| x.floor(), unit_mult: 1. });
+        a.insert("ceil", Function::Map { f: |x| x.ceil(), unit_mult: 1. });
+        a.insert("round", Function::Map { f: |x| x.round(), unit_mult: 1. });
+        a.insert("sign", Function::Map { f: |x| if x == 0. { 0. } else { x.signum() }, unit_mult: 0. });
+        a.insert("fact", Function::Map { f: |x| puruspe::gamma(x+1.), unit_mult: 0. });
+        a.insert("gamma", Function::Map { f: |x| puruspe::gamma(x), unit_mult: 0. });
+        a.insert("digamma", Function::Map { f: digamma, unit_mult: 0. });
+        a.insert("trigamma", Function::Map { f: trigamma, unit_mult: 0. });
+        a.insert("lgamma", Function::Special { f: |x| puruspe::ln_gamma(x[0]), args: &["x"], domain: |x| (x[0] <= 0.).then_some("needs x > 0") });
+        a.insert("ln_gamma", Function::Special { f: |x| puruspe::ln_gamma(x[0]), args: &["x"], domain: |x| (x[0] <= 0.).then_some("needs x > 0") });
+        a.insert("gammp", Function::Special {
+            f: |x| incomplete_gamma(x[0], x[1]).0,
+            args: &["a", "x"],
+            domain: |x| (x[0] <= 0. || x[1] < 0.).then_some("needs a > 0 and x >= 0"),
+        });
+        a.insert("gammq", Function::Special {
+            f: |x| incomplete_gamma(x[0], x[1]).1,
+            args: &["a", "x"],
+            domain: |x| (x[0] <= 0. || x[1] < 0.).then_some("needs a > 0 and x >= 0"),
+        });
+        a.insert("invgammp", Function::Special {
+            f: |x| inverse_incomplete_gamma(x[0], x[1]),
+            args: &["p", "a"],
+            domain: |x| (!(0. ..=1.).contains(&x[0]) || x[1] <= 0.).then_some("needs 0 <= p <= 1 and a > 0"),
+        });
+        a.insert("beta", Function::Special {
+            f: |x| puruspe::beta(x[0], x[1]),
+            args: &["a", "b"],
+            domain: |x| (x[0] <= 0. || x[1] <= 0.).then_some("needs a > 0 and b > 0"),
+        });
+        a.insert("betai", Function::Special {
+            f: |x| puruspe::betai(x[0], x[1], x[2]),
+            args: &["a", "b", "x"],
+            domain: |x| match x {
+                [a, b, _] if *a <= 0. || *b <= 0. => Some("needs a > 0 and b > 0"),
+                [a, b, _] if *a > BETA_SWITCH && *b > BETA_SWITCH => Some("cannot be evaluated when a and b are both above 3000"),
+                [_, _, x] if !(0. ..=1.).contains(x) => Some("needs 0 <= x <= 1"),
+                _ => None,
+            },
+        });
+        a.insert("invbetai", Function::Special {
+            f: |x| puruspe::invbetai(x[0], x[1], x[2]),
+            args: &["p", "a", "b"],
+            domain: |x| match x {
+                [p, _, _] if !(0. ..=1.).contains(p) => Some("needs 0 <= p <= 1"),
+                [_, a, b] if *a <= 0. || *b <= 0. => Some("needs a > 0 and b > 0"),
+                [_, a, b] if *a > BETA_SWITCH && *b > BETA_SWITCH => Some("cannot be evaluated when a and b are both above 3000"),
+                _ => None,
+            },
+        });
+        a.insert("erf", Function::Special { f: |x| puruspe::erf(x[0]), args: &["x"], domain: |_| None });
+        a.insert("erfc", Function::Special { f: |x| puruspe::erfc(x[0]), args: &["x"], domain: |_| None });
+        a.insert("erfcx", Function::Special { f: |x| puruspe::error::erfcx(x[0]), args: &["x"], domain: |_| None });
+        a.insert("inverf", Function::Special {
+            f: |x| puruspe::inverf(x[0]),
+            args: &["p"],
+            domain: |x| (x[0] <= -1. || x[0] >= 1.).then_some("needs -1 < p < 1"),
+        });
+        a.insert("inverfc", Function::Special {
+            f: |x| puruspe::inverfc(x[0]),
+            args: &["p"],
+            domain: |x| (x[0] <= 0. || x[0] >= 2.).then_some("needs 0 < p < 2"),
+        });
+        a.insert("besselj", Function::Special {
+            f: |x| bessel(x[0], x[1], puruspe::Jn, puruspe::Jnu_Ynu, false),
+            args: &["nu", "x"],
+            domain: |x| bessel_domain(x, false),
+        });
+        a.insert("bessely", Function::Special {
+            f: |x| bessel(x[0], x[1], puruspe::Yn, puruspe::Jnu_Ynu, true),
+            args: &["nu", "x"],
+            domain: |x| bessel_domain(x, true),
+        });
+        a.insert("besseli", Function::Special {
+            f: |x| bessel(x[0], x[1], puruspe::In, puruspe::Inu_Knu, false),
+            args: &["nu", "x"],
+            domain: |x| bessel_domain(x, false),
+        });
+        a.insert("besselk", Function::Special {
+            f: |x| bessel(x[0], x[1], puruspe::Kn, puruspe::Inu_Knu, true),
+            args: &["nu", "x"],
+            domain: |x| bessel_domain(x, true),
+        });
+        a.insert("lambertw", Function::Special {
+            f: |x| puruspe::lambert_w0(x[0]),
+            args: &["x"],
+            domain: |x| (x[0] < -1. / std::f64::consts::E).then_some("needs x >= -1/e"),
+        });
+        a.insert("lambertwm1", Function::Special {
+            f: |x| puruspe::lambert_wm1(x[0]),
+            args: &["x"],
+            domain: |x| (x[0] < -1. / std::f64::consts::E || x[0] >= 0.).then_some("needs -1/e <= x < 0"),
+        });
+        a.insert("sum", Function::Reduce { f: |x| x.iter().sum(), keeps_unit: true });
+        a.insert("mean", Function::Reduce { f: |x| x.iter().sum::<f64>() / x.len() as f64, keeps_unit: true });
+        a.insert("min", Function::Reduce { f: |x| x.iter().copied().fold(f64::INFINITY, f64::min), keeps_unit: true });
+        a.insert("max", Function::Reduce { f: |x| x.iter().copied().fold(f64::NEG_INFINITY, f64::max), keeps_unit: true });
+        a.insert("len", Function::Reduce { f: |x| x.len() as f64, keeps_unit: false });
+        a
+    };
+
+    pub static ref UNITS: HashMap<&'static str, Number> = {
+        let mut a = HashMap::new();
+
+        // Length
+        a.insert("cm", Number::new(1., Unit::new([1., 0., 0.])));
+        a.insert("m", Number::new(1e2, Unit::new([1., 0., 0.])));
+        a.insert("km", Number::new(1e5, Unit::new([1., 0., 0.])));
+        a.insert("pc", Number::new(3.086e18, Unit::new([1., 0., 0.])));
+        a.insert("ly", Number::new(9.461e17, Unit::new([1., 0., 0.])));
+        a.insert("AU", Number::new(1.496e13, Unit::new([1., 0., 0.])));
+        a.insert("um", Number::new(1e-4, Unit::new([1., 0., 0.])));
+        a.insert("nm", Number::new(1e-7, Unit::new([1., 0., 0.])));
+        a.insert("angstrom", Number::new(1e-8, Unit::new([1., 0., 0.])));
+        
+        // Mass
+        a.insert("g", Number::new(1., Unit::new([0., 1., 0.])));
+        a.insert("msun", Number::new(1.989e33, Unit::new([0., 1., 0.])));
+        a.insert("kg", Number::new(1e3, Unit::new([0., 1., 0.])));
+
+        // Time
+        a.insert("s", Number::new(1., Unit::new([0., 0., 1.])));
+        a.insert("min", Number::new(60., Unit::new([0., 0., 1.])));
+        a.insert("hr", Number::new(3600., Unit::new([0., 0., 1.])));
+        a.insert("d", Number::new(3600.*24., Unit::new([0., 0., 1.])));
+        a.insert("yr", Number::new(3600.*24.*365.25, Unit::new([0., 0., 1.])));
+        a.insert("kyr", Number::new(3600.*24.*365.25*1000., Unit::new([0., 0., 1.])));
+
+        // Frequency
+        a.insert("Hz", Number::new(1., Unit::new([0., 0., -1.])));
+        a.insert("MHz", Number::new(1e6, Unit::new([0., 0., -1.])));
+        a.insert("GHz", Number::new(1e9, Unit::new([0., 0., -1.])));
+
+        // Power
+        a.insert("lsun", Number::new(3.839e33, Unit::new([2., 1., -3.])));
+        a.insert("W", Number::new(1e7, Unit::new([2., 1., -3.])));
+        a.insert("mW", Number::new(1e4, Unit::new([2., 1., -3.])));
+
+        // Spectral flux density
+        a.insert("Jy", Number::new(1e-23, Unit::new([0., 1., -2.])));
+        a.insert("mJy", Number::new(1e-26, Unit::new([0., 1., -2.])));
+
+        // Energy
+        a.insert("erg", Number::new(1., Unit::new([2., 1., -2.])));
+        a.insert("meV", Number::new(1.60218e-15, Unit::new([2., 1., -2.])));
+        a.insert("eV", Number::new(1.60218e-12, Unit::new([2., 1., -2.])));
+        a.insert("keV", Number::new(1.60218e-9, Unit::new([2., 1., -2.])));
+        a.insert("MeV", Number::new(1.60218e-6, Unit::new([2., 1., -2.])));
+        a.insert("GeV", Number::new(1.60218e-3, Unit::new([2., 1., -2.])));
+        a.insert("TeV", Number::new(1.60218, Unit::new([2., 1., -2.])));
+        a.insert("PeV", Number::new(1.60218e3, Unit::new([2., 1., -2.])));
+        a.insert("EeV", Number::new(1.60218e6, Unit::new([2., 1., -2.])));
+        
+        // E&M
+        a.insert("G", Number::new(1., Unit::new([-0.5, 0.5, -1.])));
+        a.insert("esu", Number::new(1., Unit::new([1.5, 0.5, -1.])));
+        a.insert("dyn", Number::new(1., Unit::new([1., 1., -2.])));
+
+        // Angles and temperatures are plain numbers, in radians and kelvin
+        a.insert("rad", Number::new(1., Unit::one()));
+        a.insert("deg", Number::new(std::f64::consts::PI / 180., Unit::one()));
+        a.insert("arcmin", Number::new(std::f64::consts::PI / 180. / 60., Unit::one()));
+        a.insert("arcsec", Number::new(std::f64::consts::PI / 180. / 3600., Unit::one()));
+        a.insert("mas", Number::new(std::f64::consts::PI / 180. / 3600e3, Unit::one()));
+        a.insert("K", Number::new(1., Unit::one()));
+
+        a
+    };
+
+    /// Logarithmic scales, which a level such as 20 ABmag is written on
+    pub static ref SCALES: HashMap<&'static str, Scale> = {
+        let mut a = HashMap::new();
+        a.insert("ABmag", Scale { factor: -2.5, reference: 3631e-23, unit: Unit::new([0., 1., -2.]) });
+        a.insert("Vegamag", Scale { factor: -2.5, reference: 3640e-23, unit: Unit::new([0., 1., -2.]) });
+        a.insert("mag", Scale { factor: -2.5, reference: 1., unit: Unit::one() });
+        a.insert("dex", Scale { factor: 1., reference: 1., unit: Unit::one() });
+        a.insert("dB", Scale { factor: 10., reference: 1., unit: Unit::one() });
+        a.insert("dBm", Scale { factor: 10., reference: 1e4, unit: Unit::new([2., 1., -3.]) });
+        a
+    };
+
+    /// One-line descriptions of the default names, shown by help
+    pub static ref DESCRIPTIONS: HashMap<&'static str, &'static str> = {
+        let mut a = HashMap::new();
+
+        // Numbers
+        a.insert("pi", "ratio of a circle's circumference to its diameter");
+        a.insert("e", "base of the natural logarithm");
+        a.insert("egamma", "Euler-Mascheroni constant");
+        a.insert("inf", "infinity, for the limits of integrals");
+
+        // Constants
+        a.insert("electron_mass", "electron mass");
+        a.insert("proton_mass", "proton mass");
+        a.insert("electron_charge", "elementary charge");
+        a.insert("GN", "Newton's gravitational constant");
+        a.insert("h", "Planck constant");
+        a.insert("hbar", "reduced Planck constant");
+        a.insert("c", "speed of light");
+        a.insert("kb", "Boltzmann constant, per kelvin");
+
+        // Functions
+        a.insert("sqrt", "square root");
+        a.insert("cbrt", "cube root");
+        a.insert("exp", "exponential");
+        a.insert("ln", "natural logarithm");
+        a.insert("log10", "base 10 logarithm");
+        a.insert("log2", "base 2 logarithm");
+        a.insert("sin", "sine of an angle in radians");
+        a.insert("cos", "cosine of an angle in radians");
+        a.insert("tan", "tangent of an angle in radians");
+        a.insert("asin", "inverse sine, in radians");
+        a.insert("acos", "inverse cosine, in radians");
+        a.insert("atan", "inverse tangent, in radians");
+        a.insert("sinh", "hyperbolic sine");
+        a.insert("cosh", "hyperbolic cosine");
+        a.insert("tanh", "hyperbolic tangent");
+        a.insert("asinh", "inverse hyperbolic sine");
+        a.insert("acosh", "inverse hyperbolic cosine");
+        a.insert("atanh", "inverse hyperbolic tangent");
+        a.insert("abs", "absolute value");
+        a.insert("floor", "largest whole number of CGS units not above x");
+        a.insert("ceil", "smallest whole number of CGS units not below x");
+        a.insert("round", "nearest whole number of CGS units, halves away from zero");
+        a.insert("sign", "-1, 0 or 1 as x is negative, zero or positive");
+        a.insert("fact", "factorial, gamma(x+1)");
+        a.insert("gamma", "gamma function");
+        a.insert("digamma", "digamma function, the derivative of ln(gamma(x))");
+        a.insert("trigamma", "trigamma function, the derivative of digamma(x)");
+        a.insert("lgamma", "natural logarithm of the gamma function");
+        a.insert("ln_gamma", "natural logarithm of the gamma function");
+        a.insert("gammp", "regularized lower incomplete gamma function P(a, x)");
+        a.insert("gammq", "regularized upper incomplete gamma function Q(a, x) = 1 - P(a, x)");
+        a.insert("invgammp", "the x for which P(a, x) = p");
+        a.insert("beta", "beta function, gamma(a) gamma(b) / gamma(a + b)");
+        a.insert("betai", "regularized incomplete beta function I_x(a, b)");
+        a.insert("invbetai", "the x for which I_x(a, b) = p");
+        a.insert("erf", "error function");
+        a.insert("erfc", "complementary error function, 1 - erf(x)");
+        a.insert("erfcx", "scaled complementary error function, exp(x^2) erfc(x)");
+        a.insert("inverf", "inverse error function");
+        a.insert("inverfc", "inverse complementary error function");
+        a.insert("besselj", "Bessel function of the first kind J_nu(x)");
+        a.insert("bessely", "Bessel function of the second kind Y_nu(x)");
+        a.insert("besseli", "modified Bessel function of the first kind I_nu(x)");
+        a.insert("besselk", "modified Bessel function of the second kind K_nu(x)");
+        a.insert("lambertw", "principal branch of the Lambert W function, the w >= -1 for which w exp(w) = x");
+        a.insert("lambertwm1", "lower branch of the Lambert W function, the w <= -1 for which w exp(w) = x");
+        a.insert("sum", "sum of the elements of an array");
+        a.insert("mean", "mean of the elements of an array");
+        a.insert("max", "largest element of an array");
+        a.insert("len", "number of elements of an array");
+
+        // Units
+        a.insert("cm", "centimetre");
+        a.insert("m", "metre");
+        a.insert("km", "kilometre");
+        a.insert("pc", "parsec");
+        a.insert("ly", "light year");
+        a.insert("AU", "astronomical unit");
+        a.insert("um", "micrometre");
+        a.insert("nm", "nanometre");
+        a.insert("angstrom", "ångström, 1e-8 cm");
+        a.insert("g", "gram");
+        a.insert("msun", "solar mass");
+        a.insert("kg", "kilogram");
+        a.insert("s", "second");
+        // Also the function min, which lookups tell apart by the parentheses of a call
+        a.insert("min", "minute, or as min(x) the smallest element of an array");
+        a.insert("hr", "hour");
+        a.insert("d", "day");
+        a.insert("yr", "Julian year");
+        a.insert("kyr", "thousand Julian years");
+        a.insert("Hz", "hertz");
+        a.insert("MHz", "megahertz");
+        a.insert("GHz", "gigahertz");
+        a.insert("lsun", "solar luminosity");
+        a.insert("W", "watt");
+        a.insert("mW", "milliwatt");
+        a.insert("Jy", "jansky");
+        a.insert("mJy", "millijansky");
+        a.insert("erg", "erg");
+        a.insert("meV", "milli-electronvolt");
+        a.insert("eV", "electronvolt");
+        a.insert("keV", "kilo-electronvolt");
+        a.insert("MeV", "mega-electronvolt");
+        a.insert("GeV", "giga-electronvolt");
+        a.insert("TeV", "tera-electronvolt");
+        a.insert("PeV", "peta-electronvolt");
+        a.insert("EeV", "exa-electronvolt");
+        a.insert("G", "gauss");
+        a.insert("esu", "electrostatic unit of charge (statcoulomb)");
+        a.insert("dyn", "dyne");
+        a.insert("rad", "radian, which is 1");
+        a.insert("deg", "degree of arc, pi/180");
+        a.insert("arcmin", "minute of arc");
+        a.insert("arcsec", "second of arc");
+        a.insert("mas", "milliarcsecond");
+        a.insert("K", "kelvin, which is 1: temperatures are plain numbers, as kb is per kelvin");
+
+        // Scales
+        a.insert("ABmag", "AB magnitude, with zero point 3631 Jy");
+        a.insert("Vegamag", "Vega magnitude in the Johnson V band, with zero point 3640 Jy");
+        a.insert("mag", "magnitude difference, for flux ratios");
+        a.insert("dex", "decimal exponent: 3 dex is 1000");
+        a.insert("dB", "decibel, for power ratios");
+        a.insert("dBm", "decibel-milliwatt, for power relative to 1 mW");
+
+        a
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Context, error::CalcError};
+
+    #[test]
+    fn rounding_keeps_units() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.eval("floor(2.5 cm)").unwrap(), ctx.eval("2 cm").unwrap());
+        assert_eq!(ctx.eval("ceil(2.5 cm)").unwrap(), ctx.eval("3 cm").unwrap());
+        assert_eq!(ctx.eval("round(-2.5 g)").unwrap(), ctx.eval("-3 g").unwrap());
+        assert_eq!(ctx.eval("abs(-2 s)").unwrap(), ctx.eval("2 s").unwrap());
+    }
+
+    #[test]
+    fn overflow_is_not_a_domain_error() {
+        let mut ctx = Context::new();
+        assert!(matches!(ctx.eval("sqrt(-1)"), Err(CalcError::Domain(_))));
+        assert!(matches!(ctx.eval("acosh(0.5)"), Err(CalcError::Domain(_))));
+        assert_eq!(ctx.eval("exp(1000)"), Err(CalcError::Overflow("exp(1000) overflows to inf".to_owned())));
+        assert_eq!(ctx.eval("ln(0)"), Err(CalcError::Overflow("ln(0) overflows to -inf".to_owned())));
+        assert!(ctx.eval("exp(700)").is_ok());
+    }
+
+    #[test]
+    fn functions_map_over_arrays_and_reductions_collapse_them() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.eval("sqrt([4, 9] cm^2)").unwrap(), ctx.eval("[2, 3] cm").unwrap());
+        assert_eq!(ctx.eval("sum([1, 2, 5] km)").unwrap(), ctx.eval("8 km").unwrap());
+        assert_eq!(ctx.eval("mean([1, 2] km)").unwrap(), ctx.eval("1.5 km").unwrap());
+        assert_eq!(ctx.eval("min([3, 1, 2] s)").unwrap(), ctx.eval("1 s").unwrap());
+        assert_eq!(ctx.eval("max([3, 1, 2] s)").unwrap(), ctx.eval("3 s").unwrap());
+        assert_eq!(ctx.eval("len([3, 1, 2] s)").unwrap(), ctx.eval("3").unwrap());
+        assert!(matches!(ctx.eval("[1 km, 2 s]"), Err(CalcError::Unit(_))));
+    }
+
+    #[test]
+    fn electromagnetic_and_solar_units_have_their_dimensions() {
+        let mut ctx = Context::new();
+        let dimension = |ctx: &mut Context, text: &str| ctx.eval(text).unwrap().u.dimension_name();
+        assert_eq!(dimension(&mut ctx, "esu"), Some("charge"));
+        assert_eq!(dimension(&mut ctx, "electron_charge"), Some("charge"));
+        assert_eq!(dimension(&mut ctx, "dyn"), Some("force"));
+        assert_eq!(dimension(&mut ctx, "lsun"), Some("power"));
+        // Coulomb's law in CGS: two charges of 1 esu 1 cm apart repel with 1 dyn, and their energy is 1 erg
+        assert_eq!(ctx.eval("esu^2 / (1 cm)^2").unwrap(), ctx.eval("1 dyn").unwrap());
+        assert_eq!(ctx.eval("esu^2 / (1 cm)").unwrap(), ctx.eval("1 erg").unwrap());
+        assert!(ctx.eval("lsun * 1 s - 3.839e33 erg").is_ok());
+    }
+}
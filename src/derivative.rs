@@ -0,0 +1,37 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::Expr, number::Number, numeric, symbolic, unit::Unit};
+
+/// `diff(expr, x, at)`: the derivative of expr with respect to x at a point, or `diff(expr, x)` and `d/dx(expr)`: the derivative as an expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct Derivative {
+    pub expr: Expr,
+    pub variable: String,
+    /// The point at which to take the derivative numerically, or None to take it symbolically
+    pub at: Option<Expr>,
+}
+
+impl Derivative {
+    /// The derivative at the point, whose unit is the unit of the expression divided by the unit of the variable. Without a point, the variable must have a value and the derivative is evaluated there.
+    pub fn eval(&self, ctx: &Context) -> Result<Number> {
+        // Stored symbols are expanded first, so that both forms see through y = x^2
+        let expr = symbolic::expand(&self.expr, ctx);
+        let Some(at) = &self.at else {
+            return symbolic::derivative(&expr, &self.variable)?.eval(ctx);
+        };
+        let at = at.eval(ctx)?.quantity("The point of a derivative")?;
+        let Some(x) = at.value() else {
+            return Err(CalcError::Domain("The point at which to take a derivative must be a single number".to_owned()));
+        };
+        let mut scope = ctx.clone();
+        let mut expr_unit = None;
+        let slope = numeric::derivative(|x| {
+            scope.set_variable(&self.variable, Number::new(x, at.u));
+            let n = expr.eval(&scope)?;
+            if expr_unit.is_some_and(|u| u != n.u) {
+                return Err(CalcError::Unit(format!("The expression changes units near {}", at)));
+            }
+            expr_unit = Some(n.u);
+            n.value().ok_or_else(|| CalcError::Domain("The expression must have a single value, not an array".to_owned()))
+        }, x)?;
+        Ok(Number::new(slope, expr_unit.unwrap_or(Unit::one()) - at.u))
+    }
+}
@@ -0,0 +1,203 @@
+use std::ops::{Div, Mul, Sub};
+
+use crate::{context::Context, error::{CalcError, Result}, expr::{BinaryOp, Expr}, format::Format, number::Number, symbolic, unit::Unit, util::fraction};
+
+/// An exact fraction, so that exponents come out as 1/2 rather than 0.49999999999999994
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ratio {
+    num: i64,
+    /// Always positive, and with no factor in common with num
+    den: i64,
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+impl Ratio {
+    pub fn new(num: i64, den: i64) -> Self {
+        let divisor = gcd(num, den).max(1) * den.signum();
+        Self { num: num / divisor, den: den / divisor }
+    }
+
+    /// The fraction equal to x, if it is a simple one
+    pub fn from_f64(x: f64) -> Option<Self> {
+        fraction(x).map(|(num, den)| Self::new(num as i64, den as i64))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.num == 0
+    }
+}
+
+impl Sub for Ratio {
+    type Output = Self;
+    fn sub(self, b: Self) -> Self {
+        Self::new(self.num * b.den - b.num * self.den, self.den * b.den)
+    }
+}
+
+impl Mul for Ratio {
+    type Output = Self;
+    fn mul(self, b: Self) -> Self {
+        Self::new(self.num * b.num, self.den * b.den)
+    }
+}
+
+impl Div for Ratio {
+    type Output = Self;
+    fn div(self, b: Self) -> Self {
+        Self::new(self.num * b.den, self.den * b.num)
+    }
+}
+
+/// The exponents x for which the columns of a matrix raised to x and multiplied reach the target, and a basis of the exponents which reach nothing, by Gauss-Jordan elimination. None if the target cannot be reached.
+pub fn solve(columns: &[[Ratio; 3]], target: [Ratio; 3]) -> Option<(Vec<Ratio>, Vec<Vec<Ratio>>)> {
+    let zero = Ratio::new(0, 1);
+    let n = columns.len();
+    // Rows of the augmented matrix, one per base unit
+    let mut rows: Vec<Vec<Ratio>> = (0..3)
+        .map(|i| columns.iter().map(|c| c[i]).chain([target[i]]).collect())
+        .collect();
+    let mut pivots = Vec::new();
+    for col in 0..n {
+        let row = pivots.len();
+        let Some(found) = (row..rows.len()).find(|&r| !rows[r][col].is_zero()) else {
+            continue;
+        };
+        rows.swap(row, found);
+        let pivot = rows[row][col];
+        rows[row] = rows[row].iter().map(|x| *x / pivot).collect();
+        for other in 0..rows.len() {
+            if other != row && !rows[other][col].is_zero() {
+                let factor = rows[other][col];
+                rows[other] = rows[other].iter().zip(&rows[row]).map(|(x, p)| *x - factor * *p).collect();
+            }
+        }
+        pivots.push(col);
+    }
+    // A row with no pivot left must not ask for anything
+    if rows[pivots.len()..].iter().any(|row| !row[n].is_zero()) {
+        return None;
+    }
+
+    let mut exponents = vec![zero; n];
+    for (row, col) in pivots.iter().enumerate() {
+        exponents[*col] = rows[row][n];
+    }
+    let groups = (0..n)
+        .filter(|col| !pivots.contains(col))
+        .map(|free| {
+            let mut group = vec![zero; n];
+            group[free] = Ratio::new(1, 1);
+            for (row, col) in pivots.iter().enumerate() {
+                group[*col] = zero - rows[row][free];
+            }
+            whole(group)
+        })
+        .collect();
+    Some((exponents, groups))
+}
+
+/// Scale exponents to the smallest whole numbers with the same ratios
+fn whole(exponents: Vec<Ratio>) -> Vec<Ratio> {
+    let lcm = exponents.iter().fold(1, |lcm, x| lcm / gcd(lcm, x.den) * x.den);
+    let scaled: Vec<i64> = exponents.iter().map(|x| x.num * (lcm / x.den)).collect();
+    let divisor = scaled.iter().fold(0, |d, x| gcd(d, *x)).max(1);
+    scaled.into_iter().map(|x| Ratio::new(x / divisor, 1)).collect()
+}
+
+/// The result of dimensional analysis: a product of powers with the target dimension, and products which are dimensionless
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dimensions {
+    /// The name of the target dimension
+    pub target: String,
+    pub combination: Expr,
+    pub value: Number,
+    /// The independent dimensionless groups and their values
+    pub groups: Vec<(Expr, Number)>,
+}
+
+impl Dimensions {
+    /// One line for the combination and one for each dimensionless group, with its value
+    pub fn format(&self, format: &Format) -> String {
+        let mut lines = vec![format!("{}: {} = {}", self.target, self.combination.format(format), self.value.format(format))];
+        lines.extend(self.groups.iter().map(|(group, value)| format!("dimensionless: {} = {}", group.format(format), value.format(format))));
+        lines.join("\n")
+    }
+}
+
+/// `dimsolve(length, GN, c, hbar, M = 1 msun)`: the quantities to combine into a dimension, labelled by their text or name
+#[derive(Clone, Debug, PartialEq)]
+pub struct DimSolve {
+    pub target: String,
+    pub quantities: Vec<(String, Expr)>,
+}
+
+impl DimSolve {
+    /// The powers of the quantities whose product has the target dimension, and the independent dimensionless products of them. Only the units of the quantities matter, but the values are multiplied out too.
+    pub fn eval(&self, ctx: &Context) -> Result<Dimensions> {
+        let Some(target) = Unit::dimension(&self.target) else {
+            let names: Vec<&str> = Unit::dimension_names().collect();
+            return Err(CalcError::Domain(format!("Unknown dimension {}. The dimensions are {}", self.target, names.join(", "))));
+        };
+        let ratios = |u: Unit, what: &str| -> Result<[Ratio; 3]> {
+            let [cm, g, s] = u.exponents().map(Ratio::from_f64);
+            match (cm, g, s) {
+                (Some(cm), Some(g), Some(s)) => Ok([cm, g, s]),
+                _ => Err(CalcError::Unit(format!("The unit of {} has exponents which are not simple fractions", what))),
+            }
+        };
+        let mut values = Vec::new();
+        let mut columns = Vec::new();
+        for (label, expr) in &self.quantities {
+            let value = expr.eval(ctx).map_err(|e| match e {
+                CalcError::UnknownName(name) if name == *label => {
+                    CalcError::Domain(format!("{} has no value. Give it one for its dimension, as in {} = 1 msun", name, name))
+                },
+                e => e,
+            })?;
+            if value.is_array() {
+                return Err(CalcError::Domain(format!("{} must be a single number, not an array", label)));
+            }
+            columns.push(ratios(value.u, label)?);
+            values.push(value);
+        }
+        let names: Vec<&str> = self.quantities.iter().map(|(label, _)| label.as_str()).collect();
+        let Some((exponents, groups)) = solve(&columns, ratios(target, &self.target)?) else {
+            return Err(CalcError::Unit(format!("No product of powers of {} has dimension {}", names.join(", "), self.target)));
+        };
+
+        // A product of powers as an expression, with a name that is not one word in parentheses, and its value
+        let product = |powers: &[Ratio]| -> Result<(Expr, Number)> {
+            let mut expr = Expr::Number(Number::new(1., Unit::one()));
+            let mut value = Number::new(1., Unit::one());
+            for ((label, power), n) in names.iter().zip(powers).zip(&values) {
+                if power.is_zero() {
+                    continue;
+                }
+                let power = Number::new(power.to_f64(), Unit::one());
+                let name = if label.chars().all(|c| c.is_alphanumeric() || c == '_') { label.to_string() } else { format!("({})", label) };
+                let factor = Expr::Binary(BinaryOp::Expon, Box::new(Expr::Name(name)), Box::new(Expr::Number(power.clone())));
+                expr = Expr::Binary(BinaryOp::Mul, Box::new(expr), Box::new(factor));
+                value = value.mul(n.clone().expon(power)?)?;
+            }
+            Ok((symbolic::simplify(&expr)?, value))
+        };
+        let (combination, value) = product(&exponents)?;
+        Ok(Dimensions {
+            target: self.target.clone(),
+            combination,
+            value,
+            // A quantity given twice makes a group which simplifies to 1
+            groups: groups.iter()
+                .map(|group| product(group))
+                .filter(|group| !matches!(group, Ok((Expr::Number(_), _))))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt::Display;
+
+/// Everything that can go wrong while evaluating a line
+#[derive(Clone, Debug, PartialEq)]
+pub enum CalcError {
+    /// The text is not a valid expression
+    Parse(String),
+    /// A name which is not a unit, constant, function or variable
+    UnknownName(String),
+    /// An operation between numbers whose units are incompatible
+    Unit(String),
+    /// A function or operator was used in a way it does not support
+    Domain(String),
+    /// A finite input whose result is too large to represent, as in exp(1000)
+    Overflow(String),
+    /// An assert statement which did not hold
+    Assertion(String),
+}
+
+pub type Result<T> = std::result::Result<T, CalcError>;
+
+impl CalcError {
+    /// A short machine-readable name for the kind of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CalcError::Parse(_) => "parse",
+            CalcError::UnknownName(_) => "unknown_name",
+            CalcError::Unit(_) => "unit",
+            CalcError::Domain(_) => "domain",
+            CalcError::Overflow(_) => "overflow",
+            CalcError::Assertion(_) => "assertion",
+        }
+    }
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalcError::Parse(s) | CalcError::Unit(s) | CalcError::Domain(s) | CalcError::Overflow(s) | CalcError::Assertion(s) => write!(f, "{}", s),
+            CalcError::UnknownName(name) => write!(f, "The name {} is not a unit, constant, function or variable", name),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
@@ -0,0 +1,414 @@
+use std::fmt::Display;
+
+use crate::{context::Context, convert::Convert, defaults::Function, derivative::Derivative, dimensions::DimSolve, error::{CalcError, Result}, format::{Format, Notation, Precision}, integral::Integral, number::{Magnitude, Number}, plot::Plot, scaling::Scaling, solve::Solve, table::Sweep, unit::Unit, util::{round_eps, EPSILON}};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// The remainder after division, with the sign of the divisor
+    Mod,
+    Expon,
+}
+
+/// A parsed expression. Names are resolved when the expression is evaluated, so parsing needs no context.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(Number),
+    /// A unit, constant or variable
+    Name(String),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    /// `[1, 2, 5]`, whose elements must share a unit
+    List(Vec<Expr>),
+    Solve(Box<Solve>),
+    Integral(Box<Integral>),
+    Derivative(Box<Derivative>),
+    /// `20 ABmag`: a level on a logarithmic scale, which stands for the quantity it measures
+    Level(Box<Expr>, String),
+}
+
+/// One of the things a print statement prints
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrintItem {
+    Text(String),
+    Expr(Expr),
+}
+
+/// How far apart the two sides of an assertion may be
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tolerance {
+    /// `within 1%`, relative to the larger side
+    Percent(Expr),
+    /// `within 1 km`
+    Absolute(Expr),
+}
+
+/// What an assert statement checks
+#[derive(Clone, Debug, PartialEq)]
+pub enum Assertion {
+    /// `a == b`, optionally within a tolerance
+    Equal(Expr, Expr, Option<Tolerance>),
+    /// `dim(a) == energy`
+    Dimension(Expr, String),
+}
+
+/// A parsed line
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Expr(Expr),
+    /// `name = expression`
+    Assign(String, Expr),
+    /// `print "label:" expression ...`
+    Print(Vec<PrintItem>),
+    /// `assert a == b within 1%`
+    Assert(Assertion),
+    /// `table(expression, x, a .. b, count, log)`, with the text of the expression to label its column
+    Table(Expr, String, Sweep),
+    /// `scaling(expr, M = 1 msun, ...)`
+    Scaling(Scaling),
+    /// `dimsolve(length, GN, c, hbar)`
+    DimSolve(DimSolve),
+    /// `plot(expression, x, a .. b)`
+    Plot(Plot),
+    /// `expression in km / hr` or `expression in ABmag`, optionally `with` equivalencies such as spectral
+    Convert(Convert),
+}
+
+impl Expr {
+    /// Evaluate the expression. This never changes the context.
+    pub fn eval(&self, ctx: &Context) -> Result<Number> {
+        match self {
+            Expr::Number(n) => Ok(n.clone()),
+            Expr::Name(name) if ctx.variable(name).is_none() && let Some(definition) = ctx.symbol(name) => definition.eval(ctx),
+            Expr::Name(name) => match ctx.lookup(name) {
+                Some(n) => Ok(n),
+                None if ctx.function(name).is_some() => Err(CalcError::Parse(format!("{} is a function. Call it as {}(...)", name, name))),
+                None => Err(CalcError::UnknownName(name.clone())),
+            },
+            Expr::Neg(e) => e.eval(ctx)?.neg(),
+            Expr::Binary(op, a, b) => {
+                let a = a.eval(ctx)?;
+                let b = b.eval(ctx)?;
+                match op {
+                    BinaryOp::Add => a.add(b),
+                    BinaryOp::Sub => a.sub(b),
+                    BinaryOp::Mul => a.mul(b),
+                    BinaryOp::Div => a.div(b),
+                    BinaryOp::Mod => a.rem(b),
+                    BinaryOp::Expon => a.expon(b),
+                }
+            },
+            Expr::Call(func, args) => {
+                let Some(function) = ctx.function(func) else {
+                    return Err(CalcError::UnknownName(func.clone()));
+                };
+                // Functions of one number, or of the elements of one array
+                let one = || {
+                    if args.len() != 1 {
+                        return Err(CalcError::Domain(format!("{} takes one argument but was given {}", func, args.len())));
+                    }
+                    args[0].eval(ctx)?.quantity(&format!("The argument of {}", func))
+                };
+                match function {
+                    Function::Map { f, unit_mult } => {
+                        let n = one()?;
+                        if unit_mult == 0. && !n.u.is_one() {
+                            let unit = n.u.format(&Format::default());
+                            return Err(CalcError::Unit(format!(
+                                "{} needs a number without units, but {} has units. Divide it by a reference value first, as in {}(x / (1{}))",
+                                func, n, func, unit,
+                            )));
+                        }
+                        // A value which is not a number only comes from outside the domain, as in sqrt(-1), and an infinite one from a pole or overflow, as in ln(0) or exp(1000)
+                        let q = Magnitude::zip_all(std::slice::from_ref(&n.q), |x| {
+                            let (x, y) = (x[0], f(x[0]));
+                            if y.is_nan() && !x.is_nan() {
+                                Err(CalcError::Domain(format!("{} is not defined at {}", func, x)))
+                            } else if y.is_infinite() && x.is_finite() {
+                                Err(CalcError::Overflow(format!("{}({}) overflows to {}", func, x, y)))
+                            } else {
+                                Ok(y)
+                            }
+                        })?;
+                        Ok(Number::from_magnitude(q, n.u * unit_mult))
+                    },
+                    Function::Reduce { f, keeps_unit } => {
+                        let n = one()?;
+                        Ok(Number::new(f(n.values()), if keeps_unit { n.u } else { Unit::one() }))
+                    },
+                    Function::Special { f, args: names, domain } => {
+                        if args.len() != names.len() {
+                            let count = if names.len() == 1 { "one argument".to_owned() } else { format!("{} arguments", names.len()) };
+                            return Err(CalcError::Domain(format!("{} takes {} but was given {}", function.usage(func), count, args.len())));
+                        }
+                        let mut magnitudes = Vec::new();
+                        for (arg, name) in args.iter().zip(names) {
+                            let n = arg.eval(ctx)?.quantity(&format!("The argument {} of {}", name, func))?;
+                            if !n.u.is_one() {
+                                return Err(CalcError::Unit(format!(
+                                    "{} needs {} without units, but {} has units. Divide it by a reference value first",
+                                    function.usage(func), name, n,
+                                )));
+                            }
+                            magnitudes.push(n.q);
+                        }
+                        let q = Magnitude::zip_all(&magnitudes, |x| {
+                            if x.iter().any(|x| x.is_nan()) {
+                                return Ok(f64::NAN);
+                            }
+                            match domain(x) {
+                                Some(reason) => Err(CalcError::Domain(format!("{} {}", function.usage(func), reason))),
+                                None => Ok(f(x)),
+                            }
+                        })?;
+                        Ok(Number::from_magnitude(q, Unit::one()))
+                    },
+                }
+            },
+            Expr::List(items) => {
+                let mut values = Vec::new();
+                let mut unit = None;
+                let mut scale = None;
+                for (i, item) in items.iter().enumerate() {
+                    let n = item.eval(ctx)?;
+                    let Some(q) = n.value() else {
+                        return Err(CalcError::Domain("An array cannot contain arrays".to_owned()));
+                    };
+                    if unit.is_some_and(|u| u != n.u) {
+                        return Err(CalcError::Unit("The elements of an array must have the same units".to_owned()));
+                    }
+                    if i > 0 && scale != n.scale {
+                        return Err(CalcError::Unit("The elements of an array must all be levels on the same scale, or all be quantities".to_owned()));
+                    }
+                    unit = Some(n.u);
+                    scale = n.scale;
+                    values.push(q);
+                }
+                Ok(Number { scale, ..Number::array(values, unit.unwrap_or(Unit::one())) })
+            },
+            Expr::Solve(solve) => solve.eval(ctx),
+            Expr::Integral(integral) => integral.eval(ctx),
+            Expr::Derivative(derivative) => derivative.eval(ctx),
+            // A level keeps its scale until in converts it to the quantity it measures
+            Expr::Level(level, name) => {
+                if ctx.scale(name).is_none() {
+                    return Err(CalcError::UnknownName(name.clone()));
+                }
+                let n = level.eval(ctx)?.quantity("The number before a scale")?;
+                if !n.u.is_one() {
+                    return Err(CalcError::Unit(format!("A level in {} must be a plain number, but {} has units", name, level)));
+                }
+                Ok(Number::level(n.q, name))
+            },
+        }
+    }
+}
+
+impl Assertion {
+    /// Check the assertion, returning a description of it if it holds and an Assertion error if it does not
+    pub fn check(&self, ctx: &Context) -> Result<String> {
+        match self {
+            Assertion::Equal(a, b, tolerance) => {
+                let a = a.eval(ctx)?;
+                let b = b.eval(ctx)?;
+                if a.u != b.u {
+                    return Err(CalcError::Assertion(format!("Assertion failed: {} and {} have different units", a, b)));
+                }
+                if a.scale != b.scale {
+                    return Err(CalcError::Assertion(format!("Assertion failed: {} and {} are not levels on the same scale", a, b)));
+                }
+                // Arrays are compared element by element, and the worst element decides
+                let difference = a.q.zip(&b.q, |x, y| (x - y).abs())?;
+                let scale = a.q.zip(&b.q, |x, y| x.abs().max(y.abs()))?;
+                let pairs: Vec<(f64, f64)> = difference.values().iter().copied().zip(scale.values().iter().copied()).collect();
+                let (holds, within) = match tolerance {
+                    None => (pairs.iter().all(|(d, s)| *d <= EPSILON * s), String::new()),
+                    Some(Tolerance::Percent(amount)) => {
+                        let amount = amount.eval(ctx)?.quantity("A tolerance")?;
+                        let Some(percent) = amount.value().filter(|_| amount.u.is_one()) else {
+                            return Err(CalcError::Unit("A tolerance in percent must be a single number without units".to_owned()));
+                        };
+                        (pairs.iter().all(|(d, s)| *d <= percent.abs() / 100. * s), format!(" within {}%", percent))
+                    },
+                    Some(Tolerance::Absolute(amount)) => {
+                        let amount = amount.eval(ctx)?.quantity("A tolerance")?;
+                        let Some(limit) = amount.value().filter(|_| amount.u == a.u) else {
+                            return Err(CalcError::Unit(format!("The tolerance {} must be a single number with the units of {}", amount, a)));
+                        };
+                        (pairs.iter().all(|(d, _)| *d <= limit.abs()), format!(" within {}", amount))
+                    },
+                };
+                if holds {
+                    Ok(format!("Passed: {} == {}{}", a, b, within))
+                } else {
+                    let off = match tolerance {
+                        Some(Tolerance::Absolute(_)) => {
+                            let worst = pairs.iter().map(|(d, _)| *d).fold(0., f64::max);
+                            Number::new(worst, a.u).to_string()
+                        },
+                        _ => {
+                            let worst = pairs.iter().map(|(d, s)| d / s).fold(0., f64::max);
+                            format!("{}%", Format { notation: Notation::Auto, precision: Some(Precision::Significant(3)) }.apply(worst * 100.))
+                        },
+                    };
+                    Err(CalcError::Assertion(format!("Assertion failed: {} != {}{} (they differ by {})", a, b, within, off)))
+                }
+            },
+            Assertion::Dimension(value, name) => {
+                let value = value.eval(ctx)?;
+                let Some(unit) = Unit::dimension(name) else {
+                    let names: Vec<&str> = Unit::dimension_names().collect();
+                    return Err(CalcError::Domain(format!("Unknown dimension {}. The dimensions are {}", name, names.join(", "))));
+                };
+                if value.u == unit {
+                    Ok(format!("Passed: {} has dimension {}", value, name))
+                } else {
+                    let actual = match value.u.dimension_name() {
+                        Some(dimension) => dimension.to_owned(),
+                        None => value.u.to_string().trim().to_owned(),
+                    };
+                    Err(CalcError::Assertion(format!("Assertion failed: {} has dimension {}, not {}", value, actual, name)))
+                }
+            },
+        }
+    }
+}
+
+/// How tightly an expression binds, for deciding where printing needs parentheses
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary(BinaryOp::Add | BinaryOp::Sub, _, _) => 1,
+        Expr::Binary(BinaryOp::Mul, a, _) if has_units(a) => 2,
+        Expr::Binary(BinaryOp::Div | BinaryOp::Mod, _, _) => 2,
+        // Juxtaposition binds more tightly than * and /
+        Expr::Binary(BinaryOp::Mul, _, _) => 3,
+        Expr::Number(n) if !n.u.is_one() => 3,
+        Expr::Level(..) => 4,
+        // -a b is written without parentheses, so it binds like a b
+        Expr::Neg(e) => precedence(e).min(5),
+        Expr::Number(n) if n.value().is_some_and(|q| q < 0.) => 5,
+        Expr::Binary(BinaryOp::Expon, _, _) => 6,
+        _ => 7,
+    }
+}
+
+/// Whether an expression is written with a leading minus, which would be read as subtraction after another operand
+fn negative(expr: &Expr) -> bool {
+    match expr {
+        Expr::Neg(_) => true,
+        Expr::Number(n) => n.value().is_some_and(|q| q < 0.),
+        Expr::Level(level, _) => negative(level),
+        _ => false,
+    }
+}
+
+/// Whether text ends with a name rather than a number or a parenthesis
+fn ends_with_name(text: &str) -> bool {
+    let word = text.rsplit(|c: char| !(c.is_alphanumeric() || c == '_')).next().unwrap_or_default();
+    word.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+/// Whether a number has no more significant figures than the format prints
+fn short(q: f64, format: &Format) -> bool {
+    let digits = format.significant_figures(q);
+    let scientific = format!("{:e}", q);
+    let mantissa = scientific.split('e').next().unwrap_or_default();
+    mantissa.chars().filter(char::is_ascii_digit).count() <= digits
+}
+
+fn has_units(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(n) if !n.u.is_one())
+}
+
+impl Expr {
+    /// Print the expression in the syntax it is parsed from, so the output can be read back in, with numbers in the given format
+    pub fn format(&self, format: &Format) -> String {
+        // An operand, in parentheses if it binds less tightly than the operator around it
+        let operand = |expr: &Expr, at_least: u8| {
+            if precedence(expr) < at_least {
+                format!("({})", expr.format(format))
+            } else {
+                expr.format(format)
+            }
+        };
+        let list = |items: &[Expr]| items.iter().map(|item| item.format(format)).collect::<Vec<_>>().join(", ");
+        match self {
+            // Written numbers such as the 2 in 2 x are kept as written unless they have more digits than the format
+            Expr::Number(n) if n.u.is_one() && let Some(q) = n.value() && short(q, format) => Format::default().apply(q),
+            Expr::Number(n) => n.format(format).trim().to_owned(),
+            Expr::Name(name) => name.clone(),
+            // -(a b) is (-a) b, and the same goes for quotients, so they need no parentheses. -20 dex is a level of -20.
+            Expr::Neg(e) => format!("-{}", operand(e, match **e {
+                Expr::Binary(BinaryOp::Mul | BinaryOp::Div, _, _) => 2,
+                _ => 5,
+            })),
+            // Exponents are usually simple fractions, written as 1/2 rather than 0.5
+            Expr::Binary(BinaryOp::Expon, a, b) if let Expr::Number(n) = &**b && n.u.is_one() && let Some(power) = n.value() => {
+                let power = round_eps(power, format);
+                if power.contains('/') {
+                    format!("{}^({})", operand(a, 7), power)
+                } else {
+                    format!("{}^{}", operand(a, 7), power)
+                }
+            },
+            // Multiplication is written as juxtaposition, after which a leading minus would be read as subtraction. Products can be regrouped, so a · b c needs no parentheses.
+            Expr::Binary(BinaryOp::Mul, a, b) if !has_units(a) => {
+                let product = |e: &Expr| matches!(e, Expr::Binary(BinaryOp::Mul, ..));
+                let juxtapose = |a: &Expr, b: &Expr| {
+                    let left = if product(a) { 2 } else { 3 };
+                    let right = if negative(b) { 8 } else if product(b) { 3 } else { 4 };
+                    let (a, b) = (operand(a, left), operand(b, right));
+                    // A name followed by parentheses would be read as a call
+                    let call = b.starts_with('(') && ends_with_name(&a);
+                    (!call).then(|| format!("{} {}", a, b))
+                };
+                // Otherwise the factors are swapped, as in (1 - x)^2 x, or set apart
+                juxtapose(a, b)
+                    .or_else(|| juxtapose(b, a))
+                    .unwrap_or_else(|| format!("({} · {})", operand(a, 2), operand(b, 3)))
+            },
+            Expr::Binary(op, a, b) => {
+                let (symbol, left, right) = match op {
+                    BinaryOp::Add => (" + ", 1, 2),
+                    BinaryOp::Sub => (" - ", 1, 2),
+                    // A number with units is set apart from the names it multiplies, which could otherwise be read as more units
+                    BinaryOp::Mul if has_units(a) => (" · ", 2, 3),
+                    BinaryOp::Mul => unreachable!("products without units are written as juxtaposition"),
+                    BinaryOp::Div => (" / ", 2, 3),
+                    BinaryOp::Mod => (" % ", 2, 3),
+                    BinaryOp::Expon => ("^", 7, 5),
+                };
+                format!("{}{}{}", operand(a, left), symbol, operand(b, right))
+            },
+            Expr::Call(name, args) => format!("{}({})", name, list(args)),
+            Expr::List(items) => format!("[{}]", list(items)),
+            Expr::Solve(solve) => {
+                let rhs = solve.rhs.as_ref().map(|rhs| format!(" == {}", rhs.format(format))).unwrap_or_default();
+                let end = solve.end.as_ref().map(|end| format!(" .. {}", end.format(format))).unwrap_or_default();
+                format!("solve({}{}, {}, {}{})", solve.lhs.format(format), rhs, solve.variable, solve.start.format(format), end)
+            },
+            Expr::Integral(integral) => format!(
+                "integrate({}, {}, {}, {})",
+                integral.integrand.format(format),
+                integral.variable,
+                integral.from.format(format),
+                integral.to.format(format),
+            ),
+            Expr::Derivative(derivative) => match &derivative.at {
+                Some(at) => format!("diff({}, {}, {})", derivative.expr.format(format), derivative.variable, at.format(format)),
+                None => format!("diff({}, {})", derivative.expr.format(format), derivative.variable),
+            },
+            Expr::Level(level, name) => format!("{} {}", operand(level, 5), name),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(&Format::default()))
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt::Display;
+use crate::{error::{CalcError, Result}, util::round_eps};
+
+/// The notation used to print a floating point number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    /// Positional notation for moderate magnitudes, scientific otherwise
+    Auto,
+    /// Always positional notation
+    Fixed,
+    /// Always scientific notation
+    Sci,
+    /// Scientific notation with the exponent a multiple of three
+    Eng,
+}
+
+/// How many digits a format prints
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// A number of significant figures, as in `sci 4`
+    Significant(usize),
+    /// A number of digits after the decimal point, as in `.3e` or `.2f`
+    Decimals(usize),
+}
+
+/// How numbers (and irrational unit exponents) are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Format {
+    pub notation: Notation,
+    /// None prints up to DEFAULT_DIGITS significant figures, without trailing zeros, so that floating point noise such as 1.9890000000000002e33 is hidden
+    pub precision: Option<Precision>,
+}
+
+/// The significant figures printed when a format gives no precision
+const DEFAULT_DIGITS: usize = 10;
+
+/// Auto notation switches to scientific outside of 10^AUTO_MIN_EXP <= |x| < 10^(AUTO_MAX_EXP+1)
+const AUTO_MIN_EXP: i32 = -4;
+const AUTO_MAX_EXP: i32 = 5;
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            notation: Notation::Auto,
+            precision: None,
+        }
+    }
+}
+
+impl Format {
+    /// Parse a format specification. Accepts a mode name optionally followed by a number of significant figures (`sci 4`, `eng3`, `auto`) or the compact form of printf, where the number counts digits after the point (`.3e`, `.3f`, `.3eng`), except in `.3g`, where it counts significant figures.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let compact = spec.starts_with('.');
+        let (name, digits) = if let Some(compact) = spec.strip_prefix('.') {
+            let split = compact.find(|c: char| !c.is_ascii_digit()).unwrap_or(compact.len());
+            (&compact[split..], &compact[..split])
+        } else {
+            let split = spec.find(|c: char| c.is_ascii_digit()).unwrap_or(spec.len());
+            (spec[..split].trim(), spec[split..].trim())
+        };
+
+        let notation = match name {
+            "" | "g" | "auto" => Notation::Auto,
+            "f" | "fix" | "fixed" | "sig" => Notation::Fixed,
+            "e" | "sci" | "scientific" => Notation::Sci,
+            "eng" | "engineering" => Notation::Eng,
+            _ => return Err(CalcError::Parse(format!("Unknown format {}. Use auto, fixed, sci or eng", spec))),
+        };
+        let precision = if digits.is_empty() {
+            None
+        } else {
+            match digits.parse::<usize>() {
+                Ok(d) if compact && notation != Notation::Auto => Some(Precision::Decimals(d)),
+                Ok(0) => return Err(CalcError::Parse("A format needs at least one significant figure".to_owned())),
+                Ok(d) => Some(Precision::Significant(d)),
+                Err(_) => return Err(CalcError::Parse(format!("Could not read the number of digits in {}", spec))),
+            }
+        };
+        if name.is_empty() && precision.is_none() {
+            return Err(CalcError::Parse("Empty format specification".to_owned()));
+        }
+        Ok(Self { notation, precision })
+    }
+
+    /// The number of significant figures with which x is printed
+    pub fn significant_figures(&self, x: f64) -> usize {
+        let places = match self.precision {
+            None => return DEFAULT_DIGITS,
+            Some(Precision::Significant(d)) => return d,
+            Some(Precision::Decimals(d)) => d,
+        };
+        // The digits before the point depend on the exponent, which rounding can raise, as 9.9996 becomes 10.000 with .3f
+        let before_point = |exp: i32| match self.is_positional(x, exp) {
+            true => exp + 1,
+            false if self.notation == Notation::Eng => exp.rem_euclid(3) + 1,
+            false => 1,
+        };
+        let (_, _, exp) = decompose(x, None);
+        let first = (before_point(exp) + places as i32).max(1) as usize;
+        let (_, _, rounded) = decompose(x, Some(first));
+        (before_point(rounded) + places as i32).max(1) as usize
+    }
+
+    /// Whether x with the decimal exponent exp is printed without an exponent
+    fn is_positional(&self, x: f64, exp: i32) -> bool {
+        match self.notation {
+            Notation::Fixed => true,
+            Notation::Sci | Notation::Eng => false,
+            Notation::Auto => x == 0. || (AUTO_MIN_EXP..=AUTO_MAX_EXP).contains(&exp),
+        }
+    }
+
+    /// Print a unit exponent, as a simple fraction where possible
+    pub fn apply_exponent(&self, x: f64) -> String {
+        round_eps(x, self)
+    }
+
+    /// Print a number according to this format
+    pub fn apply(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return format!("{}", x);
+        }
+        // Decimals in positional notation do not depend on the exponent, and may round small numbers to zero
+        if let Some(Precision::Decimals(places)) = self.precision && self.is_positional(x, decompose(x, None).2) {
+            return format!("{:.*}", places, x);
+        }
+        let (negative, mut digits, exp) = decompose(x, Some(self.significant_figures(x)));
+        if self.precision.is_none() {
+            let kept = digits.trim_end_matches('0').len().max(1);
+            digits.truncate(kept);
+        }
+        let body = match self.notation {
+            _ if self.is_positional(x, exp) => positional(&digits, exp),
+            Notation::Eng => engineering(&digits, exp),
+            _ => scientific(&digits, exp),
+        };
+        if negative {
+            format!("-{}", body)
+        } else {
+            body
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.notation {
+            Notation::Auto => "auto",
+            Notation::Fixed => "fixed",
+            Notation::Sci => "sci",
+            Notation::Eng => "eng",
+        };
+        match self.precision {
+            Some(Precision::Significant(d)) => write!(f, "{} {}", name, d),
+            Some(Precision::Decimals(d)) => write!(f, ".{}{}", d, match self.notation {
+                Notation::Fixed => "f",
+                Notation::Sci => "e",
+                _ => name,
+            }),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Split a finite number into its sign, its significant digits and the decimal exponent of the first digit
+fn decompose(x: f64, digits: Option<usize>) -> (bool, String, i32) {
+    let sci = match digits {
+        Some(d) => format!("{:.*e}", d - 1, x.abs()),
+        None => format!("{:e}", x.abs()),
+    };
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp = exp.parse::<i32>().unwrap();
+    (x.is_sign_negative() && x != 0., mantissa.replace('.', ""), exp)
+}
+
+/// Place the decimal point in a digit string, with integer_digits digits before it
+fn place_point(digits: &str, integer_digits: usize) -> String {
+    if digits.len() <= integer_digits {
+        format!("{}{}", digits, "0".repeat(integer_digits - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..integer_digits], &digits[integer_digits..])
+    }
+}
+
+fn positional(digits: &str, exp: i32) -> String {
+    if exp >= 0 {
+        place_point(digits, exp as usize + 1)
+    } else {
+        format!("0.{}{}", "0".repeat((-exp - 1) as usize), digits)
+    }
+}
+
+fn scientific(digits: &str, exp: i32) -> String {
+    format!("{}e{}", place_point(digits, 1), exp)
+}
+
+fn engineering(digits: &str, exp: i32) -> String {
+    let eng_exp = exp.div_euclid(3) * 3;
+    let mantissa = place_point(digits, (exp - eng_exp) as usize + 1);
+    if eng_exp == 0 {
+        mantissa
+    } else {
+        format!("{}e{}", mantissa, eng_exp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, script::split_format, unit::Unit};
+
+    fn apply(spec: &str, x: f64) -> String {
+        Format::parse(spec).unwrap().apply(x)
+    }
+
+    #[test]
+    fn auto_hides_floating_point_noise() {
+        let auto = Format::default();
+        assert_eq!(auto.apply(0.1 + 0.2), "0.3");
+        assert_eq!(auto.apply(1.989e33 * 3. / 3.), "1.989e33");
+        assert_eq!(auto.apply(1. / 3.), "0.3333333333");
+        assert_eq!(auto.apply(123456.), "123456");
+        assert_eq!(auto.apply(1234567.), "1.234567e6");
+        assert_eq!(auto.apply(0.0001), "0.0001");
+        assert_eq!(auto.apply(0.00001), "1e-5");
+        assert_eq!(auto.apply(-2.5), "-2.5");
+        assert_eq!(apply("auto 3", 1234.5), "1230");
+    }
+
+    #[test]
+    fn modes_count_significant_figures() {
+        assert_eq!(apply("sig 3", std::f64::consts::PI), "3.14");
+        assert_eq!(apply("fixed 3", 12345.), "12300");
+        assert_eq!(apply("sci 4", 1.989e33), "1.989e33");
+        assert_eq!(apply("sci 3", 0.00012345), "1.23e-4");
+        assert_eq!(apply("eng 3", 12345.), "12.3e3");
+        assert_eq!(apply("eng4", 0.00012345), "123.4e-6");
+        assert_eq!(apply("eng 2", 12.), "12");
+        assert_eq!(apply(".3g", std::f64::consts::PI), "3.14");
+    }
+
+    #[test]
+    fn compact_forms_count_digits_after_the_point() {
+        assert_eq!(apply(".3e", 1.989e33), "1.989e33");
+        assert_eq!(apply(".0e", 1.989e33), "2e33");
+        assert_eq!(apply(".3f", std::f64::consts::PI), "3.142");
+        assert_eq!(apply(".2f", 123456.), "123456.00");
+        // Rounding up adds a digit before the point, not after it
+        assert_eq!(apply(".3f", 9.9996), "10.000");
+        assert_eq!(apply(".3e", 9.9996), "1.000e1");
+        assert_eq!(apply(".1eng", 12345.), "12.3e3");
+        assert_eq!(Format::parse(".3e").unwrap().to_string(), ".3e");
+        assert_eq!(Format::parse("sci 3").unwrap().to_string(), "sci 3");
+        assert!(Format::parse("sci 0").is_err());
+        assert!(Format::parse("bogus").is_err());
+    }
+
+    #[test]
+    fn a_line_can_choose_its_format() {
+        let (expr, spec) = split_format("msun :: .3e").unwrap();
+        let n = Context::new().eval(expr).unwrap();
+        assert_eq!(n.format(&Format::parse(spec).unwrap()), "1.989e33 g");
+        assert_eq!(n.format(&Format::parse("sci 2").unwrap()), "2.0e33 g");
+    }
+
+    #[test]
+    fn unit_exponents_are_fractions_where_possible() {
+        let format = Format::default();
+        assert_eq!(Unit::new([1., 0., -1.]).format(&format), " cm s^-1");
+        assert_eq!(Unit::new([-1.5, 0.5, 0.]).format(&format), " cm^-3/2 g^1/2");
+        assert_eq!(Unit::new([std::f64::consts::SQRT_2, 0., 0.]).format(&format), " cm^1.414213562");
+        assert_eq!(Unit::new([std::f64::consts::SQRT_2, 0., 0.]).format(&Format::parse("sci 2").unwrap()), " cm^1.4e0");
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+
+use crate::{context::Context, convert::EQUIVALENCIES, format::Format, number::Number};
+
+/// Names closer than this (by Jaro-Winkler similarity) are suggested as near matches
+const SIMILARITY_THRESHOLD: f64 = 0.8;
+/// Apropos matches words in descriptions closer than this to the search term
+const WORD_SIMILARITY_THRESHOLD: f64 = 0.9;
+/// The most near matches suggested at once
+const MAX_SUGGESTIONS: usize = 5;
+
+/// What a name refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Variable,
+    Function,
+    Number,
+    Constant,
+    Unit,
+    Scale,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Variable => "variable",
+            Kind::Function => "function",
+            Kind::Number => "number",
+            Kind::Constant => "constant",
+            Kind::Unit => "unit",
+            Kind::Scale => "scale",
+        }
+    }
+}
+
+/// Every name in the context and what it refers to
+fn entries(ctx: &Context) -> Vec<(&str, Kind)> {
+    let mut out: Vec<(&str, Kind)> = Vec::new();
+    out.extend(ctx.variables().map(|(name, _)| (name, Kind::Variable)));
+    out.extend(ctx.functions().map(|(name, _)| (name, Kind::Function)));
+    out.extend(ctx.numbers().map(|(name, _)| (name, Kind::Number)));
+    out.extend(ctx.constants().map(|(name, _)| (name, Kind::Constant)));
+    out.extend(ctx.units().map(|(name, _)| (name, Kind::Unit)));
+    out.extend(ctx.scales().map(|(name, _)| (name, Kind::Scale)));
+    out.sort_by(|a, b| sort_key(a.0).cmp(&sort_key(b.0)).then(a.1.cmp(&b.1)));
+    out
+}
+
+/// Sort names alphabetically, ignoring case
+fn sort_key(name: &str) -> (String, &str) {
+    (name.to_lowercase(), name)
+}
+
+/// The value of a name, with its dimension if it has one
+fn value_text(n: &Number) -> String {
+    let value = n.format(&Format::default());
+    match n.u.dimension_name() {
+        Some("dimensionless") | None => value,
+        Some(dimension) => format!("{} [{}]", value, dimension),
+    }
+}
+
+/// The columns of the line describing a name: how it is written, its value or unit rule, and its description
+fn entry(ctx: &Context, name: &str, kind: Kind) -> [String; 3] {
+    let description = ctx.description(name).unwrap_or("").to_owned();
+    let (label, value) = match kind {
+        Kind::Function => {
+            let function = ctx.function(name).unwrap();
+            (function.usage(name), function.unit_rule())
+        },
+        Kind::Number => (name.to_owned(), ctx.constant(name).unwrap().format(&Format::default())),
+        Kind::Variable => (name.to_owned(), value_text(&ctx.variable(name).unwrap())),
+        Kind::Constant => (name.to_owned(), value_text(&ctx.constant(name).unwrap())),
+        Kind::Unit => (name.to_owned(), ctx.unit(name).unwrap().format(&Format::default())),
+        Kind::Scale => (format!("n {}", name), ctx.scale(name).unwrap().rule()),
+    };
+    [label, value, description]
+}
+
+/// Lines describing names, with each column padded to its widest entry so that the columns line up
+fn entry_lines(entries: &[[String; 3]]) -> Vec<String> {
+    let width = |column: usize| entries.iter().map(|e| e[column].chars().count()).max().unwrap_or(0);
+    let (label_width, value_width) = (width(0), width(1));
+    entries.iter()
+        .map(|[label, value, description]| format!("  {:<label_width$}  {:<value_width$}  {}", label, value, description).trim_end().to_owned())
+        .collect()
+}
+
+/// Names which are spelled similarly to the given one, best first
+fn near_matches<'a>(ctx: &'a Context, name: &str) -> Vec<&'a str> {
+    let mut scored: Vec<(f64, &str)> = entries(ctx).into_iter()
+        .map(|(candidate, _)| (strsim::jaro_winkler(&name.to_lowercase(), &candidate.to_lowercase()), candidate))
+        .filter(|(score, _)| *score >= SIMILARITY_THRESHOLD)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut out: Vec<&str> = scored.into_iter().map(|(_, candidate)| candidate).collect();
+    out.dedup();
+    out.truncate(MAX_SUGGESTIONS);
+    out
+}
+
+/// A list of the entries of one kind, with units grouped by dimension
+fn list(ctx: &Context, kind: Kind) -> String {
+    let names: Vec<&str> = entries(ctx).into_iter()
+        .filter(|(_, k)| *k == kind)
+        .map(|(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        return format!("There are no {}s\n", kind.name());
+    }
+
+    if kind != Kind::Unit {
+        let entries: Vec<[String; 3]> = names.into_iter().map(|name| entry(ctx, name, kind)).collect();
+        return entry_lines(&entries).into_iter().map(|line| line + "\n").collect();
+    }
+
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for name in names {
+        let unit = ctx.unit(name).unwrap().u;
+        let group = match unit.dimension_name() {
+            Some(dimension) => dimension.to_owned(),
+            None => format!("other ({})", unit.format(&Format::default()).trim()),
+        };
+        groups.entry(group).or_default().push(name);
+    }
+    // The columns line up across the groups as well as within them
+    let entries: Vec<[String; 3]> = groups.values().flatten().map(|name| entry(ctx, name, kind)).collect();
+    let mut lines = entry_lines(&entries).into_iter();
+    let mut out = String::new();
+    for (group, names) in groups {
+        let mut heading = group.clone();
+        heading[..1].make_ascii_uppercase();
+        out.push_str(&format!("{}\n", heading));
+        for line in lines.by_ref().take(names.len()) {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The text shown for `help topic`. An empty topic gives an overview; a topic can be a kind of name (`units`, `functions`, ...) or a name.
+pub fn help(ctx: &Context, topic: &str) -> String {
+    let topic = topic.trim();
+    let kind = match topic {
+        "" => return overview(ctx),
+        "units" | "unit" => Some(Kind::Unit),
+        "constants" | "constant" => Some(Kind::Constant),
+        "numbers" | "number" => Some(Kind::Number),
+        "functions" | "function" => Some(Kind::Function),
+        "variables" | "variable" => Some(Kind::Variable),
+        "scales" | "scale" => Some(Kind::Scale),
+        "equivalencies" | "equivalency" => return equivalencies(),
+        _ => None,
+    };
+    if let Some(kind) = kind {
+        return list(ctx, kind);
+    }
+
+    let found: Vec<Kind> = entries(ctx).into_iter()
+        .filter(|(name, _)| *name == topic)
+        .map(|(_, kind)| kind)
+        .collect();
+    if found.is_empty() {
+        let suggestions = near_matches(ctx, topic);
+        return if suggestions.is_empty() {
+            format!("No help for {}. Try apropos {}\n", topic, topic)
+        } else {
+            format!("No help for {}. Did you mean {}?\n", topic, suggestions.join(", "))
+        };
+    }
+
+    let mut out = String::new();
+    for kind in found {
+        out.push_str(&format!("{} ({})\n", topic, kind.name()));
+        if let Some(description) = ctx.description(topic) {
+            out.push_str(&format!("  {}\n", description));
+        }
+        let value = match kind {
+            Kind::Function => {
+                let function = ctx.function(topic).unwrap();
+                out.push_str(&format!("  usage: {}\n  units: {}\n", function.usage(topic), function.unit_rule()));
+                None
+            },
+            Kind::Scale => {
+                let scale = ctx.scale(topic).unwrap();
+                out.push_str(&format!("  usage: 20 {}, or x in {} for the level of x\n", topic, topic));
+                out.push_str(&format!("  level n: {}\n", scale.rule()));
+                None
+            },
+            Kind::Variable => ctx.variable(topic),
+            Kind::Number | Kind::Constant => ctx.constant(topic),
+            Kind::Unit => ctx.unit(topic),
+        };
+        if let Some(value) = value {
+            out.push_str(&format!("  value: {}\n", value.format(&Format::default())));
+            if let Some(dimension) = value.u.dimension_name() {
+                out.push_str(&format!("  dimension: {}\n", dimension));
+            }
+        }
+    }
+    out
+}
+
+/// The equivalencies `in ... with` accepts, with what they relate and an example
+fn equivalencies() -> String {
+    let mut out = String::new();
+    for (name, relates, example) in EQUIVALENCIES {
+        out.push_str(&format!("{:<18}{}\n  {}\n", name, relates, example));
+    }
+    out
+}
+
+/// The dimension of the value of a name, if it has a common one
+fn dimension(ctx: &Context, name: &str, kind: Kind) -> Option<&'static str> {
+    let value = match kind {
+        Kind::Function => None,
+        Kind::Scale => return ctx.scale(name)?.unit.dimension_name(),
+        Kind::Variable => ctx.variable(name),
+        Kind::Number | Kind::Constant => ctx.constant(name),
+        Kind::Unit => ctx.unit(name),
+    };
+    value?.u.dimension_name()
+}
+
+/// Every name whose name, description or dimension contains the term or a word spelled like it, followed by names spelled like it
+pub fn apropos(ctx: &Context, term: &str) -> String {
+    let term = term.trim();
+    let lower = term.to_lowercase();
+    let mut found = Vec::new();
+    let mut matched = Vec::new();
+    for (name, kind) in entries(ctx) {
+        let text = format!(
+            "{} {} {}",
+            name,
+            ctx.description(name).unwrap_or(""),
+            dimension(ctx, name, kind).unwrap_or(""),
+        ).to_lowercase();
+        let fuzzy = text.split(|c: char| !c.is_alphanumeric())
+            .any(|word| word.len() > 2 && strsim::jaro_winkler(&lower, word) >= WORD_SIMILARITY_THRESHOLD);
+        if text.contains(&lower) || fuzzy {
+            found.push(entry(ctx, name, kind));
+            matched.push(name);
+        }
+    }
+    let mut out: String = entry_lines(&found).into_iter().map(|line| line + "\n").collect();
+    let similar: Vec<&str> = near_matches(ctx, term).into_iter()
+        .filter(|name| !matched.contains(name))
+        .collect();
+    if !similar.is_empty() {
+        out.push_str(&format!("Similar names: {}\n", similar.join(", ")));
+    }
+    if out.is_empty() {
+        out = format!("Nothing matches {}\n", term);
+    }
+    out
+}
+
+fn overview(ctx: &Context) -> String {
+    let count = |kind: Kind| entries(ctx).iter().filter(|(_, k)| *k == kind).count();
+    format!(
+        "Write expressions like 3 km / (2 hr) and assign variables with x = 2 msun.\n\
+         Arrays like [1, 2, 5] km are computed element by element; sum, mean, min, max and len reduce them.\n\
+         Show a result in other units with 36 km / hr in m / s, or on a logarithmic scale with 1 mJy in ABmag.\n\
+         Levels such as 20 ABmag, 3 dex and -30 dBm stay on their scale: 20 ABmag + 0.5 shifts a level and 20 ABmag - 19 ABmag is 1; use in for the quantity.\n\
+         Cross dimensions with an equivalency, as in 5000 angstrom in eV with spectral.\n\
+         a % b is the remainder with the sign of b, and needs a and b in the same units.\n\
+         Special functions such as erf(x), gammp(a, x), betai(a, b, x) and besselj(nu, x) take unitless arguments.\n\
+         Tabulate with table(2 GN M / c^2, M, 1 msun .. 100 msun, 20, log); use --output csv for CSV.\n\
+         Invert formulas with solve(2 GN M / c^2 == 10 km, M, 1 msun), giving a guess or a range a .. b.\n\
+         Integrate with integrate(4 pi r^2 rho, r, 0, 1 km); limits can be inf or -inf.\n\
+         Differentiate with diff(GN M / r, r, 1 AU) at a point, or d/dx(x^2 sin(x)) for the derivative as an expression.\n\
+         Names without values stay symbolic: r = 2 GN M / c^2 gives a value once M = 10 msun is set. Scripts declare them first with :symbol M.\n\
+         Write a result as a scaling relation with scaling(sqrt(GN M / R^3), M = 1.4 msun, R = 10 km).\n\
+         Combine quantities into a dimension with dimsolve(length, GN, c, hbar, M = 1 msun).\n\
+         Plot with plot(gamma(x), x, 0.1 .. 5), adding curves before the variable and logy or loglog at the end.\n\
+         Check results with assert x == 4 msun within 1% or assert dim(x) == mass.\n\n\
+         help units          {} units, grouped by dimension\n\
+         help constants      {} physical constants\n\
+         help numbers        {} mathematical constants\n\
+         help functions      {} functions and how they treat units\n\
+         help variables      {} variables defined so far\n\
+         help scales         {} logarithmic scales, such as magnitudes and decibels\n\
+         help equivalencies  {} equivalencies for in ... with\n\
+         help NAME           details of one name\n\
+         apropos TERM        search names and descriptions\n",
+        count(Kind::Unit),
+        count(Kind::Constant),
+        count(Kind::Number),
+        count(Kind::Function),
+        count(Kind::Variable),
+        count(Kind::Scale),
+        EQUIVALENCIES.len(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The column at which each line's description starts
+    fn description_columns(text: &str, descriptions: &[&str]) -> Vec<usize> {
+        text.lines()
+            .filter_map(|line| descriptions.iter().find(|d| line.ends_with(*d)).map(|d| line.len() - d.len()))
+            .collect()
+    }
+
+    #[test]
+    fn columns_line_up() {
+        let ctx = Context::new();
+        let text = help(&ctx, "constants");
+        let columns = description_columns(&text, &["speed of light", "elementary charge", "reduced Planck constant", "proton mass"]);
+        assert_eq!(columns.len(), 4);
+        assert!(columns.iter().all(|c| *c == columns[0]), "{}", text);
+
+        let text = apropos(&ctx, "mass");
+        let columns = description_columns(&text, &["electron mass", "kilogram", "solar mass"]);
+        assert_eq!(columns.len(), 3);
+        assert!(columns.iter().all(|c| *c == columns[0]), "{}", text);
+    }
+}
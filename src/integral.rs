@@ -0,0 +1,108 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::Expr, number::Number, numeric, symbolic, unit::Unit};
+
+/// `integrate(integrand, x, a, b)`: the integral of the integrand as x goes from a to b
+#[derive(Clone, Debug, PartialEq)]
+pub struct Integral {
+    pub integrand: Expr,
+    pub variable: String,
+    pub from: Expr,
+    pub to: Expr,
+}
+
+impl Integral {
+    /// The integral, whose unit is the unit of the integrand times the unit of the variable
+    pub fn eval(&self, ctx: &Context) -> Result<Number> {
+        let from = self.from.eval(ctx)?.quantity("The lower limit of an integral")?;
+        let to = self.to.eval(ctx)?.quantity("The upper limit of an integral")?;
+        let (Some(a), Some(b)) = (from.value(), to.value()) else {
+            return Err(CalcError::Domain("The limits of an integral must be single numbers".to_owned()));
+        };
+        // A limit which is zero or infinite and written without units takes the unit of the other, or if both are, the unit the integrand needs
+        let bare = |q: f64, u: Unit| u.is_one() && (q == 0. || q.is_infinite());
+        let unit = match (bare(a, from.u), bare(b, to.u)) {
+            (true, true) => self.variable_unit(ctx)?,
+            (true, false) => to.u,
+            (false, true) => from.u,
+            (false, false) if from.u == to.u => from.u,
+            (false, false) => return Err(CalcError::Unit(format!("The limits {} and {} have different units", from, to))),
+        };
+
+        let mut scope = ctx.clone();
+        let mut integrand_unit = None;
+        let integral = numeric::integrate(|x| {
+            scope.set_variable(&self.variable, Number::new(x, unit));
+            let n = self.integrand.eval(&scope)?.quantity("The integrand")?;
+            if integrand_unit.is_some_and(|u| u != n.u) {
+                return Err(CalcError::Unit(format!("The integrand changes units within the range of {}", self.variable)));
+            }
+            integrand_unit = Some(n.u);
+            n.value().ok_or_else(|| CalcError::Domain("The integrand must have a single value, not an array".to_owned()))
+        }, a, b)?;
+        Ok(Number::new(integral, integrand_unit.unwrap_or(Unit::one()) + unit))
+    }
+
+    /// The unit of the variable for which the integrand has a value: none, or the unit of a part of the integrand which does not depend on the variable, such as the 1 km of exp(-x / (1 km)), or its square root, as for the km^2 of 1 / (1 + x^2 / (1 km^2))
+    fn variable_unit(&self, ctx: &Context) -> Result<Unit> {
+        let mut units = vec![Unit::one()];
+        candidate_units(&self.integrand, &self.variable, ctx, &mut units);
+        let roots: Vec<Unit> = units.iter().map(|u| *u * 0.5).collect();
+        units.extend(roots);
+        let mut scope = ctx.clone();
+        for unit in units {
+            scope.set_variable(&self.variable, Number::new(1., unit));
+            if !matches!(self.integrand.eval(&scope), Err(CalcError::Unit(_))) {
+                return Ok(unit);
+            }
+        }
+        Err(CalcError::Unit(format!("Could not tell the unit of {} from the integrand. Give a limit units, as in 0 km", self.variable)))
+    }
+}
+
+/// Add the units of the largest parts of expr which do not depend on x to units
+fn candidate_units(expr: &Expr, x: &str, ctx: &Context, units: &mut Vec<Unit>) {
+    if !symbolic::depends_on(expr, x) {
+        if let Ok(n) = expr.eval(ctx) && !units.contains(&n.u) {
+            units.push(n.u);
+        }
+        return;
+    }
+    match expr {
+        Expr::Neg(e) => candidate_units(e, x, ctx, units),
+        Expr::Binary(_, a, b) => {
+            candidate_units(a, x, ctx, units);
+            candidate_units(b, x, ctx, units);
+        },
+        Expr::Call(_, args) | Expr::List(args) => args.iter().for_each(|arg| candidate_units(arg, x, ctx, units)),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Context, error::CalcError};
+
+    fn close(ctx: &mut Context, text: &str, expected: &str) {
+        let (a, b) = (ctx.eval(text).unwrap(), ctx.eval(expected).unwrap());
+        assert_eq!(a.u, b.u, "{} has the unit of {}", text, expected);
+        assert!((a.value().unwrap() / b.value().unwrap() - 1.).abs() < 1e-8, "{} is {}, not {}", text, a, b);
+    }
+
+    #[test]
+    fn limits_with_units() {
+        let mut ctx = Context::new();
+        close(&mut ctx, "integrate(exp(-x / (1 km)), x, 0 km, 1 km)", "(1 - exp(-1)) km");
+        close(&mut ctx, "integrate(2 x, x, 1 s, 2 s)", "3 s^2");
+        // A bare zero or infinity takes the unit of the other limit
+        close(&mut ctx, "integrate(exp(-x / (1 km)), x, 0, 1 km)", "(1 - exp(-1)) km");
+        close(&mut ctx, "integrate(exp(-x / (1 km)), x, 1 km, inf)", "exp(-1) km");
+        assert!(matches!(ctx.eval("integrate(1, x, 1 km, 1 s)"), Err(CalcError::Unit(_))));
+    }
+
+    #[test]
+    fn bare_limits_take_the_unit_the_integrand_needs() {
+        let mut ctx = Context::new();
+        close(&mut ctx, "integrate(exp(-x / (1 km)), x, 0, inf)", "1 km");
+        close(&mut ctx, "integrate(exp(-x^2 / (1 s)^2), x, -inf, inf)", "sqrt(pi) s");
+        close(&mut ctx, "integrate(exp(-x^2), x, -inf, inf)", "sqrt(pi)");
+    }
+}
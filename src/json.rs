@@ -0,0 +1,156 @@
+use crate::{context::Outcome, error::{CalcError, Result}, format::Format, number::{Magnitude, Number}, table::Table, unit::Unit};
+
+/// Quote and escape a string for JSON
+pub fn string(s: &str) -> String {
+    let mut out = "\"".to_owned();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON number, or null if it is not finite
+pub fn float(x: f64) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// The JSON object describing the evaluation of one line of input
+pub fn line(input: &str, result: &Result<Outcome>, format: &Format) -> String {
+    match result {
+        Ok(Outcome::Value(n)) => format!(
+            "{{\"input\": {}, \"ok\": true, {}}}",
+            string(input),
+            number_fields(n, format),
+        ),
+        Ok(Outcome::Table(table)) => format!(
+            "{{\"input\": {}, \"ok\": true, \"table\": {}}}",
+            string(input),
+            table_object(table, format),
+        ),
+        Ok(Outcome::Plot(chart)) => format!(
+            "{{\"input\": {}, \"ok\": true, \"plot\": {}}}",
+            string(input),
+            table_object(&chart.table, format),
+        ),
+        Ok(Outcome::Conversion(conversion)) => format!(
+            "{{\"input\": {}, \"ok\": true, \"value\": {}, \"in\": {}, \"formatted\": {}}}",
+            string(input),
+            match &conversion.value {
+                Magnitude::Scalar(q) => float(*q),
+                Magnitude::Array(values) => array(values),
+            },
+            string(&conversion.unit),
+            string(&conversion.format(format)),
+        ),
+        Ok(outcome) => format!(
+            "{{\"input\": {}, \"ok\": true, \"output\": {}}}",
+            string(input),
+            string(&outcome.format(format)),
+        ),
+        Err(e) => format!(
+            "{{\"input\": {}, \"ok\": false, \"error\": {}}}",
+            string(input),
+            error(e),
+        ),
+    }
+}
+
+fn number_fields(n: &Number, format: &Format) -> String {
+    format!(
+        "\"value\": {}, {}, {}\"formatted\": {}",
+        match n.value() {
+            Some(q) => float(q),
+            None => array(n.values()),
+        },
+        unit_fields(&n.u, format),
+        // A level's value is on its scale, which is named alongside
+        n.scale.as_ref().map_or(String::new(), |scale| format!("\"scale\": {}, ", string(scale))),
+        string(&n.format(format)),
+    )
+}
+
+fn unit_fields(u: &Unit, format: &Format) -> String {
+    let [cm, g, s] = u.exponents();
+    format!(
+        "\"unit\": {{\"cm\": {}, \"g\": {}, \"s\": {}}}, \"unit_string\": {}",
+        float(cm),
+        float(g),
+        float(s),
+        string(u.format(format).trim()),
+    )
+}
+
+fn array(values: &[f64]) -> String {
+    format!("[{}]", values.iter().map(|q| float(*q)).collect::<Vec<_>>().join(", "))
+}
+
+fn table_object(table: &Table, format: &Format) -> String {
+    let columns: Vec<String> = table.columns.iter()
+        .map(|c| format!("{{\"name\": {}, {}, \"values\": {}}}", string(&c.name), unit_fields(&c.unit, format), array(&c.values)))
+        .collect();
+    format!("{{\"columns\": [{}]}}", columns.join(", "))
+}
+
+fn error(e: &CalcError) -> String {
+    format!("{{\"kind\": {}, \"message\": {}}}", string(e.kind()), string(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+
+    fn json(ctx: &mut Context, input: &str) -> String {
+        line(input, &ctx.execute(input), &Format::default())
+    }
+
+    #[test]
+    fn values_carry_their_units() {
+        let mut ctx = Context::new();
+        assert_eq!(json(&mut ctx, "3 km"), r#"{"input": "3 km", "ok": true, "value": 300000, "unit": {"cm": 1, "g": 0, "s": 0}, "unit_string": "cm", "formatted": "300000 cm"}"#);
+        assert_eq!(json(&mut ctx, "[1, 2] s"), r#"{"input": "[1, 2] s", "ok": true, "value": [1, 2], "unit": {"cm": 0, "g": 0, "s": 1}, "unit_string": "s", "formatted": "[1, 2] s"}"#);
+        assert_eq!(json(&mut ctx, "20 ABmag"), r#"{"input": "20 ABmag", "ok": true, "value": 20, "unit": {"cm": 0, "g": 0, "s": 0}, "unit_string": "", "scale": "ABmag", "formatted": "20 ABmag"}"#);
+        assert_eq!(json(&mut ctx, "1 km in m"), r#"{"input": "1 km in m", "ok": true, "value": 1000, "in": "m", "formatted": "1000 m"}"#);
+        // JSON has no infinity
+        assert_eq!(json(&mut ctx, "1/0"), r#"{"input": "1/0", "ok": true, "value": null, "unit": {"cm": 0, "g": 0, "s": 0}, "unit_string": "", "formatted": "inf"}"#);
+    }
+
+    #[test]
+    fn tables_are_columns() {
+        let mut ctx = Context::new();
+        assert_eq!(
+            json(&mut ctx, "table(2 x, x, 1 s .. 2 s, 2)"),
+            r#"{"input": "table(2 x, x, 1 s .. 2 s, 2)", "ok": true, "table": {"columns": [{"name": "x", "unit": {"cm": 0, "g": 0, "s": 1}, "unit_string": "s", "values": [1, 2]}, {"name": "2 x", "unit": {"cm": 0, "g": 0, "s": 1}, "unit_string": "s", "values": [2, 4]}]}}"#,
+        );
+    }
+
+    #[test]
+    fn errors_have_a_kind() {
+        let mut ctx = Context::new();
+        assert_eq!(json(&mut ctx, "sqrt(-1)"), r#"{"input": "sqrt(-1)", "ok": false, "error": {"kind": "domain", "message": "sqrt is not defined at -1"}}"#);
+        assert_eq!(json(&mut ctx, "exp(1000)"), r#"{"input": "exp(1000)", "ok": false, "error": {"kind": "overflow", "message": "exp(1000) overflows to inf"}}"#);
+        assert!(json(&mut ctx, "1 km + 1 s").contains(r#""error": {"kind": "unit", "#));
+        assert!(json(&mut ctx, "3 +").contains(r#""error": {"kind": "parse", "#));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(string("a \"b\"\n\\c\u{1}"), r#""a \"b\"\n\\c\u0001""#);
+        assert_eq!(float(f64::NAN), "null");
+        // Values are exact, unlike the formatted text
+        assert_eq!(float(0.1 + 0.2), "0.30000000000000004");
+    }
+}
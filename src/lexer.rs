@@ -0,0 +1,145 @@
+/// The kinds of token in an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Name(String),
+    /// A quoted string, used for labels
+    Str(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    /// `..`, which separates the ends of a range
+    DotDot,
+    Equals,
+    /// `==`, which compares two sides of an assertion
+    EqualsEquals,
+    Percent,
+    /// A character which cannot appear in an expression
+    Unknown(char),
+}
+
+/// A token and the byte range of the text it came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Length in bytes of the numeric literal at the start of text, including an exponent like `e-5`
+fn number_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+        // A second dot starts a range, as in 1..10
+        if bytes[i] == b'.' && bytes.get(i + 1) == Some(&b'.') {
+            break;
+        }
+        i += 1;
+    }
+    // The e is an exponent only if digits follow it; otherwise it starts a unit like eV
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'-' || bytes[j] == b'+') {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            i = j;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+    i
+}
+
+/// Split text into tokens. This never fails: characters which cannot appear in an expression become Unknown tokens, so that partial or invalid input can still be highlighted.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let kind = if c.is_ascii_digit() || (c == '.' && text[start + 1..].starts_with(|d: char| d.is_ascii_digit())) {
+            let len = number_len(&text[start..]);
+            let literal = &text[start..start + len];
+            while chars.peek().is_some_and(|(i, _)| *i < start + len) {
+                chars.next();
+            }
+            match literal.parse::<f64>() {
+                Ok(q) => TokenKind::Number(q),
+                Err(_) => TokenKind::Unknown(c),
+            }
+        } else if c == '"' {
+            chars.next();
+            let mut end = None;
+            for (i, c) in chars.by_ref() {
+                if c == '"' {
+                    end = Some(i);
+                    break;
+                }
+            }
+            match end {
+                Some(end) => TokenKind::Str(text[start + 1..end].to_owned()),
+                // An unterminated string runs to the end of the line
+                None => TokenKind::Unknown('"'),
+            }
+        } else if is_name_start(c) {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !is_name_char(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            TokenKind::Name(text[start..end].to_owned())
+        } else {
+            chars.next();
+            match c {
+                '+' => TokenKind::Plus,
+                '-' => TokenKind::Minus,
+                '*' | '·' | '×' => TokenKind::Star,
+                '/' => TokenKind::Slash,
+                '^' => TokenKind::Caret,
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                '[' => TokenKind::LBracket,
+                ']' => TokenKind::RBracket,
+                ',' => TokenKind::Comma,
+                '.' if chars.peek().is_some_and(|(_, c)| *c == '.') => {
+                    chars.next();
+                    TokenKind::DotDot
+                },
+                '=' if chars.peek().is_some_and(|(_, c)| *c == '=') => {
+                    chars.next();
+                    TokenKind::EqualsEquals
+                },
+                '=' => TokenKind::Equals,
+                '%' => TokenKind::Percent,
+                c => TokenKind::Unknown(c),
+            }
+        };
+        let end = chars.peek().map(|(i, _)| *i).unwrap_or(text.len());
+        tokens.push(Token { kind, start, end });
+    }
+    tokens
+}
@@ -0,0 +1,41 @@
+//! A calculator for quantities with CGS units. Create a [`Context`] and pass lines to [`Context::eval`].
+
+mod util;
+mod defaults;
+mod error;
+mod format;
+mod unit;
+mod number;
+mod lexer;
+mod expr;
+mod parse;
+mod numeric;
+mod solve;
+mod integral;
+mod derivative;
+mod symbolic;
+mod table;
+mod plot;
+mod scaling;
+mod dimensions;
+mod convert;
+mod context;
+pub mod json;
+pub mod help;
+pub mod markdown;
+mod script;
+
+pub use context::{Context, Outcome, Piece};
+pub use convert::{Conversion, Scale};
+pub use defaults::Function;
+pub use dimensions::Dimensions;
+pub use error::{CalcError, Result};
+pub use expr::Expr;
+pub use format::{Format, Notation, Precision};
+pub use lexer::{tokenize, Token, TokenKind};
+pub use number::{Magnitude, Number};
+pub use parse::KEYWORDS;
+pub use plot::Chart;
+pub use table::{Column, Table};
+pub use script::{annotate, annotations, continues, split_format, split_script, strip_comment, ScriptStatement, ANNOTATION};
+pub use unit::Unit;
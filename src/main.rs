@@ -1,208 +1,753 @@
-use std::{collections::HashMap, fs::File, io::{BufReader, BufRead}};
-use lazy_static::lazy_static;
-use clap::{Arg, Command};
-use rustyline::{DefaultEditor, Config, EditMode};
+use std::{cell::RefCell, fmt::Display, fs::{self, File}, io::{self, IsTerminal, Read}, path::{Path, PathBuf}, process::ExitCode};
+use clap::{Arg, ArgAction, ArgGroup, Command};
+use rustyline::{Editor, Config, EditMode, history::DefaultHistory};
 use std::env;
 
-mod util;
-mod unit;
-mod number;
-mod instruction;
-mod parse;
-use parse::parse;
-
-use crate::{number::Number, unit::Unit};
-
-lazy_static! {
-    static ref NUMBERS: HashMap<&'static str, f64> = {
-        let mut a = HashMap::new();
-        a.insert("pi", std::f64::consts::PI);
-        a.insert("e", std::f64::consts::E);
-        a.insert("egamma", 0.5772156649015328606065120900824024310421593359);
-        a
-    };
-    static ref CONSTANTS: HashMap<&'static str, Number> = {
-        let mut a = HashMap::new();
-        a.insert("electron_mass",  Number { q: 9.1093897e-28, u: Unit::new([0., 1., 0.])});
-        a.insert("proton_mass", Number { q: 1.6726231e-24, u: Unit::new([0., 1., 0.])});
-        a.insert("electron_charge", Number { q: 4.8032068e-10, u: Unit::new([1.5, -0.5, -1.])});
-        a.insert("GN", Number { q: 6.6743e-8, u: Unit::new([3., -1., -2.])});
-        a.insert("h", Number { q: (2.*std::f64::consts::PI)*1.05457266e-27, u: Unit::new([2., 1., -1.])});
-        a.insert("hbar", Number { q: 1.05457266e-27, u: Unit::new([2., 1., -1.])});
-        a.insert("c", Number { q: 2.99792458e10, u: Unit::new([1., 0., -1.])});
-        a.insert("kb", Number { q: 1.3807e-16, u: Unit::new([2., 1., -2.])}); // Also times K^-1
-        a
-    };
-    static ref FUNCTIONS: HashMap<&'static str, (fn(f64)->f64, f64)> = {
-        let mut a: HashMap<&'static str, (fn(f64)->f64, f64)> = HashMap::new();
-        a.insert("sqrt", (|x| x.sqrt(), 0.5));
-        a.insert("cbrt", (|x| x.cbrt(), 1./3.));
-        a.insert("exp", (|x| x.exp(), 0.));
-        a.insert("sin", (|x| x.sin(), 0.));
-        a.insert("cos", (|x| x.cos(), 0.));
-        a.insert("tan", (|x| x.tan(), 0.));
-        a.insert("asin", (|x| x.asin(), 0.));
-        a.insert("acos", (|x| x.acos(), 0.));
-        a.insert("atan", (|x| x.atan(), 0.));
-        a.insert("fact", (|x| puruspe::gamma(x+1.), 0.));
-        a.insert("gamma", (|x| puruspe::gamma(x), 0.));
-        a
-    };
+use calculator::{annotate, annotations, help, json, markdown, split_format, split_script, Context, Format, Outcome};
+
+mod check;
+mod repl;
+use repl::CalcHelper;
+
+/// File in the home directory whose lines are executed at startup
+const RC_FILE: &str = ".calcrc";
+/// File in the data directory where the REPL history is kept
+const HISTORY_FILE: &str = "history";
 
-    static ref UNITS: HashMap<&'static str, Number> = {
-        let mut a = HashMap::new();
-
-        // Length
-        a.insert("cm", Number { q:1., u: Unit::new([1., 0., 0.])});
-        a.insert("m", Number { q:1e2, u: Unit::new([1., 0., 0.])});
-        a.insert("km", Number { q:1e5, u: Unit::new([1., 0., 0.])});
-        a.insert("pc", Number { q:3.086e18, u: Unit::new([1., 0., 0.])});
-        a.insert("ly", Number { q:9.461e17, u: Unit::new([1., 0., 0.])});
-        a.insert("AU", Number { q:1.496e13, u: Unit::new([1., 0., 0.])});
-        
-        // Mass
-        a.insert("g", Number { q:1., u: Unit::new([0., 1., 0.])});
-        a.insert("msun", Number { q: 1.989e33, u: Unit::new([0., 1., 0.])});
-        a.insert("kg", Number { q: 1e3, u: Unit::new([0., 1., 0.])});
-
-        // Time
-        a.insert("s", Number { q:1., u: Unit::new([0., 0., 1.])});
-        a.insert("min", Number { q:60., u: Unit::new([0., 0., 1.])});
-        a.insert("hr", Number { q:3600., u: Unit::new([0., 0., 1.])});
-        a.insert("d", Number { q:3600.*24., u: Unit::new([0., 0., 1.])});
-        a.insert("yr", Number { q:3600.*24.*365.25, u: Unit::new([0., 0., 1.])});
-        a.insert("kyr", Number { q:3600.*24.*365.25*1000., u: Unit::new([0., 0., 1.])});
-
-        // Energy
-        a.insert("lsun", Number { q: 3.839e33, u: Unit::new([2., 1., -2.])});
-        a.insert("erg", Number { q:1., u: Unit::new([2., 1., -2.])});
-        a.insert("meV", Number { q:1.60218e-15, u: Unit::new([2., 1., -2.])});
-        a.insert("eV", Number { q:1.60218e-12, u: Unit::new([2., 1., -2.])});
-        a.insert("keV", Number { q:1.60218e-9, u: Unit::new([2., 1., -2.])});
-        a.insert("MeV", Number { q:1.60218e-6, u: Unit::new([2., 1., -2.])});
-        a.insert("GeV", Number { q:1.60218e-3, u: Unit::new([2., 1., -2.])});
-        a.insert("TeV", Number { q:1.60218, u: Unit::new([2., 1., -2.])});
-        a.insert("PeV", Number { q:1.60218e3, u: Unit::new([2., 1., -2.])});
-        a.insert("EeV", Number { q:1.60218e6, u: Unit::new([2., 1., -2.])});
-        
-        // E&M
-        a.insert("G", Number { q:1., u: Unit::new([-0.5, 0.5, -1.])});
-        a.insert("esu", Number { q:1., u: Unit::new([1.5, -0.5, -1.])});
-        a.insert("dyn", Number { q:1., u: Unit::new([1., 1., -1.])});
-
-        a
+/// The directory for files the calculator keeps between sessions, following the XDG convention
+fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
     };
+    Some(base.join("calc"))
 }
 
-fn print_help() {
-    println!("NUMBERS: ");
-    for k in NUMBERS.keys() {
-        println!("{}", k);
+/// The REPL commands, shown after the overview of help
+const COMMAND_HELP: &str = "\
+:format [FORMAT]  show or set the number format (auto, fixed, sci or eng, and significant figures, or .3e for digits after the point)
+:output [MODE]    show or set the output mode (text, json or csv)
+:save FILE        write this session's settings and definitions to a script
+:load FILE        run a script
+:symbol NAME...   let names without values stay symbolic in scripts, where they are otherwise errors
+EXPR :: FORMAT    print one result with a different format
+exit              leave the calculator
+";
+
+/// How results are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Text,
+    /// One JSON object per evaluated line
+    Json,
+    /// Tables as comma-separated values, and everything else as text
+    Csv,
+}
+
+impl Output {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "text" => Some(Output::Text),
+            "json" => Some(Output::Json),
+            "csv" => Some(Output::Csv),
+            _ => None,
+        }
     }
-    println!("");
 
-    println!("CONSTANTS: ");
-    for k in CONSTANTS.keys() {
-        println!("{}", k);
+    fn name(&self) -> &'static str {
+        match self {
+            Output::Text => "text",
+            Output::Json => "json",
+            Output::Csv => "csv",
+        }
     }
-    println!("");
+}
 
-    println!("FUNCTIONS: ");
-    for k in FUNCTIONS.keys() {
-        println!("{}", k);
+/// The context and settings which persist between lines
+struct Session {
+    ctx: Context,
+    format: Format,
+    output: Output,
+    /// The file and line number being executed, used to label errors
+    location: Option<(String, usize)>,
+    /// Continue running a file after a line fails
+    keep_going: bool,
+    /// Run lines without printing their results, as for the rc file. Errors are still reported.
+    quiet: bool,
+    /// The number of definitions made by the rc file, which :save leaves out since the rc file makes them again
+    rc_definitions: usize,
+    /// Errors collected instead of printed, if they are being collected
+    captured: Option<RefCell<Vec<String>>>,
+    /// The SVG file plots are written to, if any, and the number written so far
+    plot_out: Option<String>,
+    plots: usize,
+}
+
+impl Session {
+    fn new() -> Self {
+        // Scripts must declare their symbols, so that a misspelled name is an error
+        let mut ctx = Context::new();
+        ctx.set_strict(true);
+        Self {
+            ctx,
+            format: Format::default(),
+            output: Output::Text,
+            location: None,
+            keep_going: false,
+            quiet: false,
+            rc_definitions: 0,
+            captured: None,
+            plot_out: None,
+            plots: 0,
+        }
     }
-    println!("");
 
-    println!("UNITS: ");
-    for k in UNITS.keys() {
-        println!("{}", k);
+    /// Report an error on stderr, or collect it if errors are being collected
+    fn error(&self, message: impl Display) {
+        let message = match &self.location {
+            Some((file, line)) => format!("{}:{}: {}", file, line, message),
+            None => message.to_string(),
+        };
+        match &self.captured {
+            Some(captured) => captured.borrow_mut().push(message),
+            None => eprintln!("{}", message),
+        }
     }
-}
 
-fn execute_line(line: &str) {
-    if line == "" {return;}
-    if line == "help" {
-        print_help();
-        return;
+    /// Run the commands in the rc file silently, if there is one, so that its results do not mix with the output of the session
+    fn load_rc(&mut self) {
+        let Some(home) = env::var_os("HOME") else { return };
+        let path = std::path::Path::new(&home).join(RC_FILE);
+        if path.exists() {
+            self.quiet = true;
+            self.execute_file(&path.to_string_lossy());
+            self.quiet = false;
+        }
+        self.rc_definitions = self.ctx.definitions().len();
     }
-    let parsed = match parse(&line) {
-        Ok(o) => o,
-        Err(e) => {
-            println!("{}", e);
-            return;
+
+    /// Execute a line starting with `:`, which changes the settings of the session. Returns whether it succeeded.
+    fn execute_command(&mut self, command: &str) -> bool {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "format" => {
+                if args.trim().is_empty() {
+                    if !self.quiet {
+                        println!("{}", self.format);
+                    }
+                    return true;
+                }
+                match Format::parse(args) {
+                    Ok(f) => self.format = f,
+                    Err(e) => {
+                        self.error(e);
+                        return false;
+                    },
+                }
+            },
+            "output" => {
+                if args.trim().is_empty() {
+                    if !self.quiet {
+                        println!("{}", self.output.name());
+                    }
+                    return true;
+                }
+                match Output::parse(args) {
+                    Some(o) => self.output = o,
+                    None => {
+                        self.error(format!("Unknown output mode {}. Use text, json or csv", args.trim()));
+                        return false;
+                    },
+                }
+            },
+            "save" => return self.save(args.trim()),
+            "symbol" => {
+                if args.trim().is_empty() {
+                    self.error("Usage: :symbol NAME...");
+                    return false;
+                }
+                for name in args.split_whitespace() {
+                    if let Err(e) = self.ctx.declare(name) {
+                        self.error(e);
+                        return false;
+                    }
+                }
+            },
+            "load" => {
+                if args.trim().is_empty() {
+                    self.error("Usage: :load FILE");
+                    return false;
+                }
+                // Errors inside the file are labelled with its own lines
+                let location = self.location.take();
+                let ok = self.execute_file(args.trim());
+                self.location = location;
+                return ok;
+            },
+            _ => {
+                self.error(format!("Unknown command :{}", name));
+                return false;
+            },
         }
-    };
+        true
+    }
 
-    let number = match parsed.calculate() {
-        Ok(n) => n,
-        Err(e) => {
-            println!("{}", e);
-            return;
+    /// Write the settings of this session and the definitions made in it, but not those of the rc file, to a script which :load can replay
+    fn save(&self, filename: &str) -> bool {
+        if filename.is_empty() {
+            self.error("Usage: :save FILE");
+            return false;
         }
-    };
+        let mut script = String::new();
+        if self.format != Format::default() {
+            script.push_str(&format!(":format {}\n", self.format));
+        }
+        if self.output != Output::Text {
+            script.push_str(&format!(":output {}\n", self.output.name()));
+        }
+        let declared: Vec<&str> = self.ctx.declared().collect();
+        if !declared.is_empty() {
+            script.push_str(&format!(":symbol {}\n", declared.join(" ")));
+        }
+        for definition in &self.ctx.definitions()[self.rc_definitions..] {
+            script.push_str(definition);
+            script.push('\n');
+        }
+        match fs::write(filename, script) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error(format!("Could not write {}: {}", filename, e));
+                false
+            },
+        }
+    }
 
-    println!("{}", number);
-}
+    /// Split a trailing `:: spec`, which overrides the format for this line only, from a line
+    fn line_format<'a>(&self, line: &'a str) -> calculator::Result<(&'a str, Format)> {
+        match split_format(line) {
+            Some((expr, spec)) => Ok((expr, Format::parse(spec)?)),
+            None => Ok((line, self.format)),
+        }
+    }
+
+    /// Execute a line and print its result. Returns whether it succeeded.
+    fn execute_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if line.is_empty() {return true;}
+        if let Some(topic) = line.strip_prefix("help") && (topic.is_empty() || topic.starts_with(' ')) {
+            if !self.quiet {
+                print!("{}", help::help(&self.ctx, topic));
+                if topic.trim().is_empty() {
+                    print!("\n{}", COMMAND_HELP);
+                }
+            }
+            return true;
+        }
+        if let Some(term) = line.strip_prefix("apropos ") {
+            if !self.quiet {
+                print!("{}", help::apropos(&self.ctx, term));
+            }
+            return true;
+        }
+        if let Some(command) = line.strip_prefix(':') {
+            return self.execute_command(command.trim());
+        }
+
+        let (line, format) = match self.line_format(line) {
+            Ok(split) => split,
+            Err(e) => {
+                self.error(e);
+                return false;
+            },
+        };
+
+        let result = self.ctx.execute(line);
+        if self.quiet {
+            if let Err(e) = &result {
+                self.error(e);
+            }
+            return result.is_ok();
+        }
+        let mut ok = result.is_ok();
+        if let Ok(Outcome::Plot(chart)) = &result {
+            ok &= self.write_plot(&chart.svg(&format));
+        }
+        match self.output {
+            Output::Json => println!("{}", json::line(line.trim(), &result, &format)),
+            Output::Csv if let Ok(Outcome::Table(table)) = &result => println!("{}", table.csv(&format)),
+            Output::Csv if let Ok(Outcome::Plot(chart)) = &result => println!("{}", chart.table.csv(&format)),
+            Output::Text if let Ok(Outcome::Plot(chart)) = &result => println!("{}", chart.render(&format, io::stdout().is_terminal())),
+            Output::Text | Output::Csv => match result {
+                Ok(outcome) => println!("{}", outcome.format(&format)),
+                Err(e) => self.error(e),
+            },
+        }
+        ok
+    }
 
-fn interpreter() {
-    let config = Config::builder()
-        .edit_mode(EditMode::Emacs) // or Vi
-        .auto_add_history(true)
-        .build();
+    /// Write a plot to the --plot-out file, if there is one. Later plots go to numbered files beside it, as in plot-2.svg.
+    fn write_plot(&mut self, svg: &str) -> bool {
+        let Some(path) = &self.plot_out else {
+            return true;
+        };
+        self.plots += 1;
+        let path = match (self.plots, path.rsplit_once('.')) {
+            (1, _) => path.clone(),
+            (n, Some((stem, extension))) => format!("{}-{}.{}", stem, n, extension),
+            (n, None) => format!("{}-{}", path, n),
+        };
+        match fs::write(&path, svg) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error(format!("Could not write {}: {}", path, e));
+                false
+            },
+        }
+    }
 
-    let mut rl = DefaultEditor::with_config(config).unwrap();
+    /// Execute a script: statements separated by `;` or newlines, with comments and continued lines. Stops at the first failure unless keep_going is set.
+    /// Errors are labelled with name and the line number, if a name is given.
+    fn execute_script(&mut self, name: Option<&str>, source: &str) -> bool {
+        self.execute_lines(name, source, 0)
+    }
 
-    loop {
-        match rl.readline(">>> ") {
-            Ok(line) => {
-                if line == "exit" {break;}
-                execute_line(&line);
+    /// Execute a script which starts after the first `offset` lines of a file
+    fn execute_lines(&mut self, name: Option<&str>, source: &str, offset: usize) -> bool {
+        let mut ok = true;
+        for statement in split_script(source) {
+            if let Some(name) = name {
+                self.location = Some((name.to_owned(), offset + statement.line));
+            }
+            ok &= self.execute_line(&statement.text);
+            if !ok && !self.keep_going {
+                break;
             }
-            Err(rustyline::error::ReadlineError::Eof) => break,
+        }
+        if name.is_some() {
+            self.location = None;
+        }
+        ok
+    }
+
+    /// Execute a statement and return the text to annotate it with, if it has a result. Errors are reported and also returned as annotations.
+    fn annotation(&mut self, line: &str) -> (Option<String>, bool) {
+        let line = line.trim();
+        let help = line.strip_prefix("help").is_some_and(|topic| topic.is_empty() || topic.starts_with(' '));
+        if line.is_empty() || help || line.starts_with("apropos ") {
+            return (None, true);
+        }
+        if let Some(command) = line.strip_prefix(':') {
+            return (None, self.execute_command(command.trim()));
+        }
+        let result = self.line_format(line)
+            .and_then(|(line, format)| Ok(self.ctx.execute(line)?.format(&format)));
+        match result {
+            Ok(text) => (Some(text), true),
             Err(e) => {
-                eprintln!("Error: {:?}", e);
+                self.error(&e);
+                (Some(format!("error: {}", e)), false)
+            },
+        }
+    }
+
+    /// Execute a script and return it with each statement's result appended as a comment. Statements after a failure are not run unless keep_going is set.
+    fn annotate(&mut self, name: &str, source: &str) -> (String, bool) {
+        let mut ok = true;
+        let mut results = Vec::new();
+        for statement in split_script(source) {
+            self.location = Some((name.to_owned(), statement.line));
+            let (annotation, success) = self.annotation(&statement.text);
+            if let Some(annotation) = annotation {
+                results.push((statement.last_line, annotation));
+            }
+            ok &= success;
+            if !ok && !self.keep_going {
                 break;
             }
         }
+        self.location = None;
+        (annotate(source, &results), ok)
     }
-}
 
-fn execute_file(filename: &str) {
-    let file = match File::open(filename) {
-        Ok(f) => f,
-        Err(_) => {
-            println!("The file {} does not exist", filename);
-            return
-        },
-    };
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.unwrap();
-        execute_line(&line);
+    /// Annotate a script or run a Markdown document read from a file, or stdin if the filename is -, and print the result or write it back. Returns whether every statement succeeded.
+    fn rewrite_file(&mut self, filename: &str, markdown: bool, in_place: bool) -> bool {
+        let (name, source) = if filename == "-" {
+            if in_place {
+                self.error("Cannot rewrite stdin in place");
+                return false;
+            }
+            let mut source = String::new();
+            ("<stdin>", io::stdin().read_to_string(&mut source).map(|_| source))
+        } else {
+            (filename, fs::read_to_string(filename))
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                self.error(format!("Could not read {}: {}", name, e));
+                return false;
+            },
+        };
+
+        let (rewritten, ok) = if markdown {
+            self.run_markdown(name, &source)
+        } else {
+            self.annotate(name, &source)
+        };
+        if !in_place {
+            print!("{}", rewritten);
+        } else if let Err(e) = fs::write(filename, rewritten) {
+            self.error(format!("Could not write {}: {}", filename, e));
+            return false;
+        }
+        ok
+    }
+
+    /// Run the calc blocks of a Markdown document with shared state and return the document with their results beneath them. Blocks after a failure are not run unless keep_going is set.
+    fn run_markdown(&mut self, name: &str, document: &str) -> (String, bool) {
+        let mut ok = true;
+        let output = markdown::execute(document, |block| {
+            if !ok && !self.keep_going {
+                return None;
+            }
+            let mut output = String::new();
+            for statement in split_script(&block.code) {
+                self.location = Some((name.to_owned(), block.line + statement.line - 1));
+                let (result, success) = self.annotation(&statement.text);
+                if let Some(result) = result {
+                    output.push_str(&result);
+                    output.push('\n');
+                }
+                ok &= success;
+                if !ok && !self.keep_going {
+                    break;
+                }
+            }
+            Some(output)
+        });
+        self.location = None;
+        (output, ok)
+    }
+
+    /// Run a script or Markdown document and return the problems with it: failed statements, including assertions, and results which differ from those recorded in it by --annotate or by an earlier run
+    fn check_file(&mut self, path: &Path) -> Vec<String> {
+        let name = path.to_string_lossy().into_owned();
+        self.captured = Some(RefCell::new(Vec::new()));
+        self.keep_going = true;
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return vec![format!("Could not read {}: {}", name, e)],
+        };
+
+        let differences = if path.extension().is_some_and(|e| e == "md") {
+            let (document, _) = self.run_markdown(&name, &source);
+            if source.contains(&format!("```{}", markdown::OUTPUT)) {
+                check::diff(&source, &document)
+            } else {
+                Vec::new()
+            }
+        } else {
+            // Only lines which were annotated have a result to compare with. The rows of a multi-line result are compared together.
+            let (annotated, _) = self.annotate(&name, &source);
+            let mut differences = Vec::new();
+            for ((line, expected), (_, actual)) in annotations(&source).into_iter().zip(annotations(&annotated)) {
+                let Some(expected) = expected else { continue };
+                let actual = actual.unwrap_or_default();
+                if expected != actual {
+                    differences.push(format!("{}: - {}", line, expected.replace('\n', " | ")));
+                    differences.push(format!("{}: + {}", line, actual.replace('\n', " | ")));
+                }
+            }
+            differences
+        };
+
+        let mut problems = self.captured.take().map(RefCell::into_inner).unwrap_or_default();
+        problems.extend(differences.into_iter().map(|line| format!("{}:{}", name, line)));
+        problems
+    }
+
+    fn interpreter(&mut self) {
+        // Interactively, a name without a value stays symbolic and is shown as such
+        self.ctx.set_strict(false);
+        let config = Config::builder()
+            .edit_mode(EditMode::Emacs) // or Vi
+            .auto_add_history(true)
+            .build();
+
+        let mut rl: Editor<CalcHelper, DefaultHistory> = Editor::with_config(config).unwrap();
+        rl.set_helper(Some(CalcHelper::new(&self.ctx, self.format)));
+
+        let history = data_dir().map(|dir| dir.join(HISTORY_FILE));
+        if let Some(path) = &history {
+            // There is no history the first time the calculator is run
+            let _ = rl.load_history(path);
+        }
+
+        loop {
+            match rl.readline(">>> ") {
+                Ok(line) => {
+                    if line.trim() == "exit" {break;}
+                    self.execute_script(None, &line);
+                    if let Some(helper) = rl.helper_mut() {
+                        helper.update(&self.ctx, self.format);
+                    }
+                }
+                Err(rustyline::error::ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(path) = &history {
+            let saved = path.parent().map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| rl.save_history(path).map_err(io::Error::other));
+            if let Err(e) = saved {
+                eprintln!("Could not save history to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Execute the script read from reader, labelling errors with name. Returns whether every statement succeeded.
+    fn execute_reader(&mut self, name: &str, mut reader: impl Read) -> bool {
+        let mut bytes = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut bytes) {
+            self.error(format!("Could not read {}: {}", name, e));
+            return false;
+        }
+
+        // A line which is not UTF-8 is an error in its place: the lines before it run first, and those after it only with keep_going
+        let mut ok = true;
+        let mut source = String::new();
+        let mut offset = 0;
+        for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+            match std::str::from_utf8(line) {
+                Ok(line) => {
+                    source.push_str(line);
+                    source.push('\n');
+                },
+                Err(_) => {
+                    ok &= self.execute_lines(Some(name), &source, offset);
+                    if ok || self.keep_going {
+                        self.location = Some((name.to_owned(), i + 1));
+                        self.error("The line is not valid UTF-8");
+                        self.location = None;
+                    }
+                    ok = false;
+                    if !self.keep_going {
+                        return false;
+                    }
+                    source.clear();
+                    offset = i + 1;
+                },
+            }
+        }
+        self.execute_lines(Some(name), &source, offset) && ok
+    }
+
+    fn execute_file(&mut self, filename: &str) -> bool {
+        if filename == "-" {
+            return self.execute_reader("<stdin>", io::stdin().lock());
+        }
+        let file = match File::open(filename) {
+            Ok(f) => f,
+            Err(e) => {
+                self.error(format!("Could not open {}: {}", filename, e));
+                return false;
+            },
+        };
+        self.execute_reader(filename, file)
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let matches = Command::new("calc")
         .arg(
             Arg::new("code")
                 .short('c')
                 .long("code")
                 .value_name("LINE")
-                .help("Run inline code")
+                .help("Run inline code. Separate statements with ; or newlines")
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .help("Number format: auto, fixed, sci or eng, optionally followed by significant figures (e.g. \"sci 4\"), or digits after the point as in printf (e.g. .3e)")
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("MODE")
+                .value_parser(["text", "json", "csv"])
+                .help("Print results as text, as one JSON object per line, or with tables as CSV")
+        )
+        .arg(
+            Arg::new("keep-going")
+                .short('k')
+                .long("keep-going")
+                .action(ArgAction::SetTrue)
+                .help("Keep executing after a line fails. The exit status is still nonzero")
+        )
+        .arg(
+            Arg::new("annotate")
+                .long("annotate")
+                .action(ArgAction::SetTrue)
+                .help("Print the file with each result appended as a # => comment")
+        )
+        .arg(
+            Arg::new("markdown")
+                .short('m')
+                .long("markdown")
+                .action(ArgAction::SetTrue)
+                .help("Run the ```calc blocks of a Markdown file and print it with the results beneath each block. Implied for .md files")
+        )
+        .group(ArgGroup::new("rewrite").args(["annotate", "markdown"]))
+        .arg(
+            Arg::new("in-place")
+                .short('i')
+                .long("in-place")
+                .action(ArgAction::SetTrue)
+                .help("With --annotate or --markdown, rewrite the file instead of printing it")
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["code", "rewrite"])
+                .help("Run every .calc, .txt and .md file in a directory and report the failed assertions and changed results of each")
+        )
+        .arg(
+            Arg::new("plot-out")
+                .long("plot-out")
+                .value_name("FILE")
+                .help("Also write each plot as an SVG image to FILE, numbering the files after the first")
         )
         .arg(
             Arg::new("arg")
-                .help("Single argument to execute")
+                .help("File to execute, or - for stdin")
                 .num_args(1),
         )
         .get_matches_from(env::args());
 
-    if let Some(code) = matches.get_one::<String>("code") {
-        execute_line(code);
+    let mut session = Session::new();
+    session.load_rc();
+    session.keep_going = matches.get_flag("keep-going");
+    if let Some(spec) = matches.get_one::<String>("format") {
+        match Format::parse(spec) {
+            Ok(f) => session.format = f,
+            Err(e) => {
+                session.error(e);
+                return ExitCode::FAILURE;
+            },
+        }
+    }
+    session.plot_out = matches.get_one::<String>("plot-out").cloned();
+    if let Some(output) = matches.get_one::<String>("output") {
+        session.output = Output::parse(output).unwrap();
+    }
+
+    let filename = matches.get_one::<String>("arg").map(String::as_str);
+    if matches.get_flag("check") {
+        return check(&session, Path::new(filename.unwrap_or(".")));
+    }
+    let markdown = matches.get_flag("markdown")
+        || filename.is_some_and(|f| f.ends_with(".md") || f.ends_with(".markdown"));
+    if matches.get_flag("in-place") && !matches.get_flag("annotate") && !markdown {
+        session.error("--in-place only applies to --annotate and Markdown files");
+        return ExitCode::FAILURE;
+    }
+    let ok = if matches.get_flag("annotate") || markdown {
+        session.rewrite_file(filename.unwrap_or("-"), markdown, matches.get_flag("in-place"))
+    } else if let Some(code) = matches.get_one::<String>("code") {
+        session.execute_script(None, code)
     } else if let Some(arg) = matches.get_one::<String>("arg") {
-        execute_file(arg);
+        session.execute_file(arg)
+    } else if !io::stdin().is_terminal() {
+        session.execute_file("-")
+    } else {
+        session.interpreter();
+        true
+    };
+
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Run every script under a path in a fresh session with the settings of this one, and report which pass
+fn check(settings: &Session, path: &Path) -> ExitCode {
+    let scripts = match check::scripts(path) {
+        Ok(scripts) => scripts,
+        Err(e) => {
+            settings.error(format!("Could not read {}: {}", path.display(), e));
+            return ExitCode::FAILURE;
+        },
+    };
+    let mut failed = 0;
+    for script in &scripts {
+        let mut session = Session::new();
+        session.format = settings.format;
+        let problems = session.check_file(script);
+        if problems.is_empty() {
+            println!("PASS {}", script.display());
+        } else {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+    println!("{} passed, {} failed", scripts.len() - failed, failed);
+    if failed == 0 {
+        ExitCode::SUCCESS
     } else {
-        interpreter();
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_set_for_the_session_or_one_line() {
+        let mut session = Session::new();
+        session.quiet = true;
+        assert!(session.execute_command("format .3e"));
+        assert_eq!(session.format, Format::parse(".3e").unwrap());
+        assert!(!session.execute_command("format bogus"));
+        assert_eq!(session.format, Format::parse(".3e").unwrap());
+        let (line, format) = session.line_format("msun :: sci 2").unwrap();
+        assert_eq!((line, format), ("msun ", Format::parse("sci 2").unwrap()));
+        assert_eq!(session.line_format("msun").unwrap().1, session.format);
+        assert!(session.line_format("msun :: bogus").is_err());
+    }
+
+    #[test]
+    fn saved_sessions_load_back() {
+        let path = env::temp_dir().join(format!("calc-session-{}.calc", std::process::id()));
+        let path = path.to_string_lossy();
+        let mut session = Session::new();
+        session.quiet = true;
+        assert!(session.execute_script(None, ":format sci 3\n:output json\n:symbol M\nd = 3 km\narea = d^2\nr = 2 GN M / c^2\nd = 4 km"));
+        assert!(session.save(&path));
+
+        let mut restored = Session::new();
+        restored.quiet = true;
+        assert!(restored.execute_file(&path));
+        fs::remove_file(path.as_ref()).unwrap();
+        assert_eq!(restored.format, session.format);
+        assert_eq!(restored.output, Output::Json);
+        // Definitions are replayed in order, so later ones win and earlier results are kept
+        assert_eq!(restored.ctx.variable("d"), session.ctx.variable("d"));
+        assert_eq!(restored.ctx.variable("area"), session.ctx.evaluate("9 km^2").ok());
+        assert_eq!(restored.ctx.symbol("r"), session.ctx.symbol("r"));
     }
 }
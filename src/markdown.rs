@@ -0,0 +1,97 @@
+/// The info string of the fenced blocks which are evaluated
+const CALC: &str = "calc";
+/// The info string of the blocks holding results, which are replaced each time the document is run
+pub const OUTPUT: &str = "calc-output";
+
+/// A fenced ```` ```calc ```` block of a Markdown document
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeBlock {
+    /// The lines between the fences
+    pub code: String,
+    /// The line of the document the code starts on, counting from 1
+    pub line: usize,
+}
+
+/// The fence which opens a code block, as its character, its length and the info string after it
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = &line[indent..];
+    let c = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.len() - line.trim_start_matches(c).len();
+    if len < 3 {
+        return None;
+    }
+    Some((c, len, line[len..].trim()))
+}
+
+/// Whether a line closes a block opened by a fence of length len made of c
+fn closes(line: &str, c: char, len: usize) -> bool {
+    fence(line).is_some_and(|(d, n, info)| d == c && n >= len && info.is_empty())
+}
+
+/// The first word of an info string, which names the language
+fn language(info: &str) -> &str {
+    info.split_whitespace().next().unwrap_or("")
+}
+
+/// Run every calc block of a Markdown document in order and return the document with what run gives for each block in a calc-output block beneath it.
+/// Output blocks from an earlier run are replaced. If run returns None the rest of the document is left as it is.
+pub fn execute(document: &str, mut run: impl FnMut(&CodeBlock) -> Option<String>) -> String {
+    let lines: Vec<&str> = document.lines().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    let mut running = true;
+    while i < lines.len() {
+        let line = lines[i];
+        out.push_str(line);
+        out.push('\n');
+        i += 1;
+        let Some((c, len, info)) = fence(line) else { continue };
+
+        // Copy the block, finding its code
+        let start = i;
+        while i < lines.len() && !closes(lines[i], c, len) {
+            out.push_str(lines[i]);
+            out.push('\n');
+            i += 1;
+        }
+        let block = CodeBlock { code: lines[start..i].join("\n"), line: start + 1 };
+        if i < lines.len() {
+            out.push_str(lines[i]);
+            out.push('\n');
+            i += 1;
+        }
+        if !running || language(info) != CALC {
+            continue;
+        }
+
+        let Some(output) = run(&block) else {
+            // The rest of the document, including old output, is left as it is
+            running = false;
+            continue;
+        };
+
+        // Drop the output of the last run, which is separated from the block by at most one blank line
+        let next = if lines.get(i).is_some_and(|l| l.trim().is_empty()) { i + 1 } else { i };
+        if let Some((d, n, info)) = lines.get(next).and_then(|l| fence(l)) && language(info) == OUTPUT {
+            i = next + 1;
+            while i < lines.len() && !closes(lines[i], d, n) {
+                i += 1;
+            }
+            i += 1;
+        }
+        out.push_str(&format!("\n```{}\n", OUTPUT));
+        for line in output.lines() {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str("```\n");
+    }
+    if !document.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
@@ -1,142 +1,263 @@
 use std::fmt::Display;
 
-use anyhow::{anyhow, Result};
-use crate::{CONSTANTS, NUMBERS, UNITS, unit::Unit};
+use crate::{convert::hint, error::{CalcError, Result}, format::Format, unit::Unit, util::EPSILON};
 
-#[derive(Clone, Copy, Debug)]
+/// The magnitude of a number in CGS units: one value, or one for each element of an array
+#[derive(Clone, Debug, PartialEq)]
+pub enum Magnitude {
+    Scalar(f64),
+    Array(Vec<f64>),
+}
+
+impl Magnitude {
+    /// The values, of which a scalar has one
+    pub fn values(&self) -> &[f64] {
+        match self {
+            Magnitude::Scalar(q) => std::slice::from_ref(q),
+            Magnitude::Array(values) => values,
+        }
+    }
+
+    /// Apply f to each value
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Self {
+        match self {
+            Magnitude::Scalar(q) => Magnitude::Scalar(f(*q)),
+            Magnitude::Array(values) => Magnitude::Array(values.iter().map(|q| f(*q)).collect()),
+        }
+    }
+
+    /// Apply f to pairs of values. A scalar is paired with every element of an array; arrays are paired element by element and must have the same length.
+    pub fn zip(&self, other: &Self, f: impl Fn(f64, f64) -> f64) -> Result<Self> {
+        match (self, other) {
+            (Magnitude::Scalar(a), Magnitude::Scalar(b)) => Ok(Magnitude::Scalar(f(*a, *b))),
+            (Magnitude::Scalar(a), Magnitude::Array(b)) => Ok(Magnitude::Array(b.iter().map(|b| f(*a, *b)).collect())),
+            (Magnitude::Array(a), Magnitude::Scalar(b)) => Ok(Magnitude::Array(a.iter().map(|a| f(*a, *b)).collect())),
+            (Magnitude::Array(a), Magnitude::Array(b)) => {
+                if a.len() != b.len() {
+                    return Err(CalcError::Domain(format!("Cannot combine arrays with {} and {} elements", a.len(), b.len())));
+                }
+                Ok(Magnitude::Array(a.iter().zip(b).map(|(a, b)| f(*a, *b)).collect()))
+            },
+        }
+    }
+
+    /// Print the values with the given format. Arrays are printed as `[1, 2, 5]`.
+    pub fn format(&self, format: &Format) -> String {
+        match self {
+            Magnitude::Scalar(q) => format.apply(*q),
+            Magnitude::Array(values) => format!("[{}]", values.iter().map(|q| format.apply(*q)).collect::<Vec<_>>().join(", ")),
+        }
+    }
+
+    /// Apply f to the values of several magnitudes together, pairing them as zip does
+    pub fn zip_all(magnitudes: &[Magnitude], f: impl Fn(&[f64]) -> Result<f64>) -> Result<Self> {
+        let mut len = None;
+        for m in magnitudes {
+            if let Magnitude::Array(values) = m {
+                if let Some(len) = len && len != values.len() {
+                    return Err(CalcError::Domain(format!("Cannot combine arrays with {} and {} elements", len, values.len())));
+                }
+                len = Some(values.len());
+            }
+        }
+        let at = |i: usize| -> Result<f64> {
+            let x: Vec<f64> = magnitudes.iter()
+                .map(|m| match m {
+                    Magnitude::Scalar(q) => *q,
+                    Magnitude::Array(values) => values[i],
+                })
+                .collect();
+            f(&x)
+        };
+        match len {
+            None => Ok(Magnitude::Scalar(at(0)?)),
+            Some(len) => Ok(Magnitude::Array((0..len).map(at).collect::<Result<_>>()?)),
+        }
+    }
+}
+
+/// A quantity: a magnitude in CGS units and its unit. Arrays share one unit.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Number {
-    pub q: f64,
-    pub u: Unit
+    pub q: Magnitude,
+    pub u: Unit,
+    /// The logarithmic scale on which q is a level, as in 20 ABmag, or None for a quantity. A level has no unit.
+    pub scale: Option<String>,
 }
 
+/// The error for arithmetic on a level which only makes sense for quantities
+fn level_error(verb: &str, n: &Number) -> CalcError {
+    CalcError::Unit(format!("Cannot {} {}, which is a level. Shift a level by adding a plain number, or convert it with in first", verb, n))
+}
+
+// Arithmetic can fail on units, so it returns a Result instead of implementing std::ops
+#[allow(clippy::should_implement_trait)]
 impl Number {
+    pub fn new(q: f64, u: Unit) -> Self {
+        Self { q: Magnitude::Scalar(q), u, scale: None }
+    }
+
+    /// An array of values which share a unit
+    pub fn array(values: Vec<f64>, u: Unit) -> Self {
+        Self { q: Magnitude::Array(values), u, scale: None }
+    }
+
+    /// A level on the logarithmic scale called scale
+    pub fn level(q: Magnitude, scale: &str) -> Self {
+        Self { q, u: Unit::one(), scale: Some(scale.to_owned()) }
+    }
+
+    /// The number if it is a quantity rather than a level, or an error saying what cannot take a level
+    pub fn quantity(self, what: &str) -> Result<Self> {
+        match &self.scale {
+            Some(_) => Err(CalcError::Unit(format!("{} cannot be a level, but {} is. Convert it with in first", what, self))),
+            None => Ok(self),
+        }
+    }
+
+    /// The magnitude of the number in CGS units, or None if it is an array
+    pub fn value(&self) -> Option<f64> {
+        match self.q {
+            Magnitude::Scalar(q) => Some(q),
+            Magnitude::Array(_) => None,
+        }
+    }
+
+    /// The magnitudes of the elements in CGS units, of which a scalar has one
+    pub fn values(&self) -> &[f64] {
+        self.q.values()
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self.q, Magnitude::Array(_))
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.u
+    }
+
+    /// Levels can only be shifted or compared, so any other arithmetic on them is an error
+    fn no_levels(&self, b: &Self, verb: &str) -> Result<()> {
+        match [self, b].into_iter().find(|n| n.scale.is_some()) {
+            Some(level) => Err(level_error(verb, level)),
+            None => Ok(()),
+        }
+    }
+
     pub fn mul(self, b: Self) -> Result<Self> {
-        Ok(Self {
-            q: self.q * b.q,
-            u: self.u + b.u,
-        })
+        self.no_levels(&b, "multiply")?;
+        Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x * y)?, self.u + b.u))
     }
     pub fn div(self, b: Self) -> Result<Self> {
-        Ok(Self {
-            q: self.q / b.q,
-            u: self.u - b.u,
-        })
+        self.no_levels(&b, "divide")?;
+        Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x / y)?, self.u - b.u))
     }
+    /// A level plus a plain number is the level shifted by it, in either order
     pub fn add(self, b: Self) -> Result<Self> {
-        if b.u != self.u {return Err(anyhow!("Cannot add numbers with different units"));}
-        Ok(Self  {
-            q: self.q + b.q,
-            u: self.u
-        })
+        match (&self.scale, &b.scale) {
+            (Some(s), Some(t)) if s == t => return Err(CalcError::Unit(format!(
+                "Cannot add the levels {} and {}. Their difference is a plain number, but their sum is not a level; convert them with in to add the quantities they measure",
+                self, b,
+            ))),
+            (Some(_), Some(_)) => return Err(CalcError::Unit(format!("Cannot add {} and {}, which are levels on different scales", self, b))),
+            (Some(scale), None) | (None, Some(scale)) => {
+                let (level, shift) = if self.scale.is_some() { (&self, &b) } else { (&b, &self) };
+                if !shift.u.is_one() {
+                    return Err(CalcError::Unit(format!("A level can only be shifted by a plain number, but {} has units", shift)));
+                }
+                return Ok(Self::level(level.q.zip(&shift.q, |x, y| x + y)?, scale));
+            },
+            (None, None) => {},
+        }
+        if b.u != self.u {return Err(CalcError::Unit(format!("Cannot add numbers with different units{}", hint(self.u, b.u))));}
+        Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x + y)?, self.u))
     }
+    /// The difference of two levels on the same scale is a plain number, and a level minus a plain number is a shifted level
     pub fn sub(self, b: Self) -> Result<Self> {
-        if b.u != self.u {return Err(anyhow!("Cannot subtract numbers with different units"));}
-        Ok(Self {
-            q: self.q - b.q,
-            u: self.u
-        })
+        match (&self.scale, &b.scale) {
+            (Some(s), Some(t)) if s == t => return Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x - y)?, Unit::one())),
+            (Some(_), Some(_)) => return Err(CalcError::Unit(format!("Cannot subtract {} from {}, which are levels on different scales", b, self))),
+            (Some(scale), None) => {
+                if !b.u.is_one() {
+                    return Err(CalcError::Unit(format!("A level can only be shifted by a plain number, but {} has units", b)));
+                }
+                return Ok(Self::level(self.q.zip(&b.q, |x, y| x - y)?, scale));
+            },
+            (None, Some(_)) => return Err(level_error("subtract", &b)),
+            (None, None) => {},
+        }
+        if b.u != self.u {return Err(CalcError::Unit(format!("Cannot subtract numbers with different units{}", hint(self.u, b.u))));}
+        Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x - y)?, self.u))
+    }
+    /// The remainder after dividing by b, which has the sign of b, so that an angle % (2 pi) is never negative
+    pub fn rem(self, b: Self) -> Result<Self> {
+        self.no_levels(&b, "take the remainder of")?;
+        if b.u != self.u {return Err(CalcError::Unit("Cannot take the remainder of numbers with different units".to_owned()));}
+        if b.values().contains(&0.) {return Err(CalcError::Domain("Cannot take the remainder after dividing by zero".to_owned()));}
+        Ok(Self::from_magnitude(self.q.zip(&b.q, |x, y| x - y * (x / y).floor())?, self.u))
     }
     pub fn neg(self) -> Result<Self> {
-        Ok(Self {
-            q: -self.q,
-            u: self.u
-        })
+        if self.scale.is_some() {
+            return Err(level_error("negate", &self));
+        }
+        Ok(Self::from_magnitude(self.q.map(|x| -x), self.u))
     }
     pub fn expon(self, b: Self) -> Result<Self> {
-        if !b.u.is_one() {return Err(anyhow!("Exponents must be unitless"));}
-        Ok(Self {
-            q: self.q.powf(b.q),
-            u: self.u * b.q
-        })
-    }
-    
-    pub fn parse(s: &str) -> Result<Self, ()> {
-        if s.len() == 0 {
-            return Err(());
-        }
-
-        // Find the first index of a non-number character assuming e is an exponential
-        let mut first_str_index = None;
-        for (i, c) in s.chars().enumerate() {
-            if '0' <= c && c < '9' {continue;}
-            if i > 0 && (c == '.' || c == '_' || c == '-') {continue;}
-            if i > 0 && (c == 'e' || c == 'E') {
-                // Check if next character is alphanumeric
-                match s.chars().nth(i+1) {
-                    Some(cc) => {
-                        if '0' <= cc && cc < '9' {continue;} // The e is an exponential
-                        if i > 0 && (cc == '.' || cc == '_' || cc == '-') {continue;} // The e is an exponential
-                        () // The e is a character
-                    },
-                    None => (), // the e is a character
-                }
-            }
-            first_str_index = Some(i);
-            break;
-        }
-
-        match first_str_index {
-            None => {
-                // It's all numbers
-                let q = match s.parse::<f64>() {
-                    Ok(q) => q,
-                    Err(_) => return Err(())
-                };
-                Ok(Self {
-                    q,
-                    u: Unit::one(),
-                })
-            },
-            Some(index) => {
-                if index == 0 {
-                    // It's all letters
-                    let mut parse_result =  Self::parse_unit(s);
-                    if parse_result.is_err() {
-                        parse_result = Self::parse_constant(s);
-                    }
-                    parse_result
-                } else {
-                    // It's half numbers, half letters
-                    let q = match s[0..index].parse::<f64>() {
-                        Ok(q) => q,
-                        Err(_) => return Err(())
-                    };
-                    let q_num = Self {
-                        q,
-                        u: Unit::one(),
-                    };
-        
-                    let u_num = Self::parse_unit(&s[index..])?;
-        
-                    match q_num.mul(u_num) {
-                        Ok(n) => Ok(n),
-                        Err(_) => Err(())
-                    }
-                }
-            }
+        self.no_levels(&b, "raise to a power")?;
+        if !b.u.is_one() {return Err(CalcError::Unit("Exponents must be unitless".to_owned()));}
+        // Every element of an array shares a unit, so a number with units can only be raised to one power
+        let power = b.values().first().copied().unwrap_or(1.);
+        if !self.u.is_one() && b.values().iter().any(|p| (p - power).abs() > EPSILON) {
+            return Err(CalcError::Unit("A number with units cannot be raised to an array of different powers".to_owned()));
         }
+        Ok(Self::from_magnitude(self.q.zip(&b.q, f64::powf)?, self.u * power))
     }
 
-    fn parse_unit(s: &str) -> Result<Self, ()>{
-        match UNITS.get(s) {
-            Some(v) => Ok(*v),
-            None => Err(())
-        }
+    /// A quantity with a magnitude and a unit, which is not a level
+    pub fn from_magnitude(q: Magnitude, u: Unit) -> Self {
+        Self { q, u, scale: None }
     }
 
-    fn parse_constant(s: &str) -> Result<Self, ()>{
-        if let Some(v) = NUMBERS.get(s) {
-            return Ok(Self { q: *v, u: Unit::new([0., 0., 0.])})
-        }
-        if let Some(v) = CONSTANTS.get(s) {
-            return Ok(*v)
+    /// Print the number and its unit or scale with the given format. Arrays are printed as `[1, 2, 5] cm`.
+    pub fn format(&self, format: &Format) -> String {
+        match &self.scale {
+            Some(scale) => format!("{} {}", self.q.format(format), scale),
+            None => format!("{}{}", self.q.format(format), self.u.format(format)),
         }
-        Err(())
     }
 }
 
 impl Display for Number {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {}", self.q, self.u)
+        write!(f, "{}", self.format(&Format::default()))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrays_broadcast_element_by_element() {
+        let cm = Unit::new([1., 0., 0.]);
+        let lengths = Number::array(vec![1., 2., 5.], cm);
+        assert_eq!(lengths.clone().mul(Number::new(2., cm)).unwrap(), Number::array(vec![2., 4., 10.], cm * 2.));
+        assert_eq!(lengths.clone().add(Number::array(vec![1., 1., 1.], cm)).unwrap(), Number::array(vec![2., 3., 6.], cm));
+        assert_eq!(lengths.clone().expon(Number::new(2., Unit::one())).unwrap(), Number::array(vec![1., 4., 25.], cm * 2.));
+        assert_eq!(Number::new(2., Unit::one()).expon(Number::array(vec![1., 2.], Unit::one())).unwrap(), Number::array(vec![2., 4.], Unit::one()));
+        assert_eq!(lengths.format(&Format::default()), "[1, 2, 5] cm");
+        assert!(matches!(lengths.clone().add(Number::array(vec![1., 2.], cm)), Err(CalcError::Domain(_))));
+        assert!(matches!(lengths.add(Number::new(1., Unit::one())), Err(CalcError::Unit(_))));
+    }
+
+    #[test]
+    fn remainders_need_matching_units() {
+        let cm = Unit::new([1., 0., 0.]);
+        assert_eq!(Number::new(5., cm).rem(Number::new(2., cm)).unwrap(), Number::new(1., cm));
+        // The remainder has the sign of the divisor
+        assert_eq!(Number::new(-5., cm).rem(Number::new(2., cm)).unwrap(), Number::new(1., cm));
+        assert_eq!(Number::new(5., cm).rem(Number::new(-2., cm)).unwrap(), Number::new(-1., cm));
+        assert!(matches!(Number::new(5., cm).rem(Number::new(2., Unit::one())), Err(CalcError::Unit(_))));
+        assert!(matches!(Number::new(5., cm).rem(Number::new(0., cm)), Err(CalcError::Domain(_))));
+    }
+}
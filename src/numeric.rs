@@ -0,0 +1,466 @@
+use crate::error::{CalcError, Result};
+
+/// The most iterations a root finder takes before giving up
+const MAX_ITERATIONS: usize = 200;
+/// The most times a bracket search doubles its distance from the guess
+const MAX_DOUBLINGS: i32 = 64;
+/// The relative error an integral is refined to
+const INTEGRAL_TOLERANCE: f64 = 1e-10;
+/// An integral which cannot be refined further is still accepted if its relative error is below this
+const INTEGRAL_ACCEPTABLE: f64 = 1e-6;
+/// The most intervals an integral is split into
+const MAX_INTERVALS: usize = 2000;
+
+/// The nodes of the 15-point Kronrod rule on [-1, 1], from the outside in. The odd ones are also the nodes of the 7-point Gauss rule.
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6, 0.949_107_912_342_758_5, 0.864_864_423_359_769_1, 0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1, 0.405_845_151_377_397_2, 0.207_784_955_007_898_5, 0.,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22, 0.063_092_092_629_978_55, 0.104_790_010_322_250_2, 0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9, 0.190_350_578_064_785_4, 0.204_432_940_075_298_9, 0.209_482_141_084_727_8,
+];
+/// The weights of the 7-point Gauss rule, for the nodes KRONROD_NODES[1], [3], [5] and [7]
+const GAUSS_WEIGHTS: [f64; 4] = [0.129_484_966_168_869_7, 0.279_705_391_489_276_7, 0.381_830_050_505_118_9, 0.417_959_183_673_469_4];
+
+/// Evaluate f, treating a value which is not a number as an error
+fn value(f: &mut impl FnMut(f64) -> Result<f64>, x: f64) -> Result<f64> {
+    let y = f(x)?;
+    if y.is_nan() {
+        return Err(CalcError::Domain(format!("The expression is not defined at {}", x)));
+    }
+    Ok(y)
+}
+
+/// Find an interval around which f changes sign by stepping away from the guess in doubling steps: by factors of two, which keep the sign of the guess, and by adding and subtracting multiples of its size, which can cross zero
+pub fn bracket(mut f: impl FnMut(f64) -> Result<f64>, guess: f64) -> Result<(f64, f64)> {
+    if !guess.is_finite() {
+        return Err(CalcError::Domain("Give a finite guess, or a range a .. b which contains the root".to_owned()));
+    }
+    let start = value(&mut f, guess)?;
+    if start == 0. {
+        return Ok((guess, guess));
+    }
+    let size = if guess == 0. { 1. } else { guess.abs() };
+    // Points where f is not defined end the search in that direction
+    let mut last = [(guess, start); 4];
+    let mut open = [guess != 0., guess != 0., true, true];
+    for n in 1..=MAX_DOUBLINGS {
+        let step = 2f64.powi(n);
+        let offset = size * step / 2.;
+        for (side, x) in [guess * step, guess / step, guess + offset, guess - offset].into_iter().enumerate() {
+            if !open[side] {
+                continue;
+            }
+            let y = match f(x) {
+                Ok(y) if !y.is_nan() => y,
+                _ => {
+                    open[side] = false;
+                    continue;
+                },
+            };
+            let (previous, previous_y) = last[side];
+            if y == 0. || y.signum() != previous_y.signum() {
+                return Ok((previous.min(x), previous.max(x)));
+            }
+            last[side] = (x, y);
+        }
+    }
+    Err(CalcError::Domain(format!("Could not find a root near {}. Try a range a .. b which contains it", guess)))
+}
+
+/// Find a root of f between a and b, where f has opposite signs, by Brent's method, then refine it with Newton steps which stay inside the final bracket
+pub fn solve(mut f: impl FnMut(f64) -> Result<f64>, a: f64, b: f64) -> Result<f64> {
+    let (mut a, mut b) = (a, b);
+    let mut fa = value(&mut f, a)?;
+    let mut fb = value(&mut f, b)?;
+    if fa == 0. {
+        return Ok(a);
+    }
+    if fb == 0. {
+        return Ok(b);
+    }
+    if fa.signum() == fb.signum() {
+        return Err(CalcError::Domain(format!("The equation does not change sign between {} and {}", a, b)));
+    }
+    let width = (b - a).abs();
+
+    let (mut c, mut fc) = (a, fa);
+    let mut d = b - a;
+    let mut e = d;
+    for _ in 0..MAX_ITERATIONS {
+        // Keep the root between b and c, with b the better estimate
+        if fb.signum() == fc.signum() {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+        let tol = 2. * f64::EPSILON * b.abs() + 1e-15 * width;
+        let m = 0.5 * (c - b);
+        if m.abs() <= tol || fb == 0. {
+            return Ok(newton(&mut f, b, fb, b.min(c), b.max(c)));
+        }
+
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            // Inverse quadratic interpolation, or the secant method if only two points differ
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2. * m * s, 1. - s)
+            } else {
+                let q = fa / fc;
+                let r = fb / fc;
+                (s * (2. * m * q * (q - r) - (b - a) * (r - 1.)), (q - 1.) * (r - 1.) * (s - 1.))
+            };
+            if p > 0. {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            // Accept the interpolation only if it falls well inside the bracket and is converging
+            if 2. * p < (3. * m * q - (tol * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = m;
+                e = m;
+            }
+        } else {
+            d = m;
+            e = m;
+        }
+        a = b;
+        fa = fb;
+        b += if d.abs() > tol { d } else { tol.copysign(m) };
+        fb = value(&mut f, b)?;
+    }
+    Err(CalcError::Domain("The root finder did not converge".to_owned()))
+}
+
+/// Polish a root with a few Newton steps, using a central difference for the derivative. A step is kept only if it stays between lo and hi and brings f closer to zero.
+fn newton(f: &mut impl FnMut(f64) -> Result<f64>, x: f64, fx: f64, lo: f64, hi: f64) -> f64 {
+    // The root is already good to within the bracket, so a failed step keeps it rather than failing
+    let (mut x, mut fx) = (x, fx);
+    for _ in 0..3 {
+        if fx == 0. {
+            break;
+        }
+        let h = f64::EPSILON.cbrt() * x.abs().max(hi - lo);
+        let (Ok(up), Ok(down)) = (value(f, x + h), value(f, x - h)) else { break };
+        let next = x - fx * 2. * h / (up - down);
+        if !(lo..=hi).contains(&next) {
+            break;
+        }
+        let Ok(f_next) = value(f, next) else { break };
+        if f_next.abs() >= fx.abs() {
+            break;
+        }
+        x = next;
+        fx = f_next;
+    }
+    // Prefer a rounder number which satisfies the equation as well, so that 9 is not found as 8.999999999999998
+    let round: f64 = format!("{:.14e}", x).parse().unwrap_or(x);
+    match value(f, round) {
+        Ok(f_round) if f_round.abs() <= fx.abs() => round,
+        _ => x,
+    }
+}
+
+/// The 15-point Gauss-Kronrod estimate of the integral of f over [a, b], and the difference from the 7-point Gauss estimate as its error
+fn kronrod(f: &mut impl FnMut(f64) -> Result<f64>, a: f64, b: f64) -> Result<(f64, f64)> {
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+    let mut kronrod = 0.;
+    let mut gauss = 0.;
+    for (i, (node, weight)) in KRONROD_NODES.iter().zip(KRONROD_WEIGHTS).enumerate() {
+        let y = if *node == 0. {
+            value(f, center)?
+        } else {
+            value(f, center - half * node)? + value(f, center + half * node)?
+        };
+        kronrod += weight * y;
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * y;
+        }
+    }
+    Ok((kronrod * half, ((kronrod - gauss) * half).abs()))
+}
+
+/// The distance from a, in the given direction, at which most of the integral of f lies: the power of ten d where |d f(a + d)| is largest.
+/// Quantities in CGS units can have any size, so an infinite range needs this scale to be mapped onto a finite one.
+fn scale(f: &mut impl FnMut(f64) -> Result<f64>, a: f64, direction: f64) -> f64 {
+    (-40..=40)
+        .map(|k| 10f64.powi(k))
+        .filter_map(|d| match f(a + direction * d) {
+            Ok(y) if y.is_finite() => Some((d, (d * y).abs())),
+            _ => None,
+        })
+        .max_by(|x, y| x.1.total_cmp(&y.1))
+        .filter(|(_, weight)| *weight > 0.)
+        .map_or(1., |(d, _)| d)
+}
+
+/// Whether the integral of f from a towards infinity in the given direction clearly diverges, because |x f(x)| does not fall off far from a.
+/// The mapped integral cannot tell, since it only samples as far as floating point reaches.
+fn diverges(f: &mut impl FnMut(f64) -> Result<f64>, a: f64, direction: f64, scale: f64) -> bool {
+    let mut weight = |d: f64| f(a + direction * d).map(|y| (d * y).abs()).unwrap_or(0.);
+    let near = weight(scale * 1e10);
+    let far = weight(scale * 1e30);
+    far > 0. && far >= near
+}
+
+/// The integral of f over [a, b] by adaptive Gauss-Kronrod quadrature, which repeatedly splits the interval with the largest error.
+/// Infinite limits are mapped to a finite interval: [a, inf) by x = a + s t / (1 - t) and (-inf, inf) by x = s t / (1 - t^2), where s is the scale of the integrand.
+pub fn integrate(mut f: impl FnMut(f64) -> Result<f64>, a: f64, b: f64) -> Result<f64> {
+    if a.is_nan() || b.is_nan() {
+        return Err(CalcError::Domain("The limits of an integral must be numbers".to_owned()));
+    }
+    if a == b {
+        return Ok(0.);
+    }
+    if a > b {
+        return Ok(-integrate(f, b, a)?);
+    }
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => adaptive(&mut f, a, b),
+        (true, false) => {
+            let s = scale(&mut f, a, 1.);
+            if diverges(&mut f, a, 1., s) {
+                return Err(CalcError::Domain("The integral diverges".to_owned()));
+            }
+            adaptive(&mut |t: f64| towards_infinity(&mut f, a + s * t / (1. - t), s / ((1. - t) * (1. - t))), 0., 1.)
+        },
+        (false, true) => {
+            let s = scale(&mut f, b, -1.);
+            if diverges(&mut f, b, -1., s) {
+                return Err(CalcError::Domain("The integral diverges".to_owned()));
+            }
+            adaptive(&mut |t: f64| towards_infinity(&mut f, b - s * t / (1. - t), s / ((1. - t) * (1. - t))), 0., 1.)
+        },
+        (false, false) => {
+            let s = scale(&mut f, 0., 1.).max(scale(&mut f, 0., -1.));
+            if diverges(&mut f, 0., 1., s) || diverges(&mut f, 0., -1., s) {
+                return Err(CalcError::Domain("The integral diverges".to_owned()));
+            }
+            adaptive(&mut |t: f64| {
+                let d = 1. - t * t;
+                towards_infinity(&mut f, s * t / d, s * (1. + t * t) / (d * d))
+            }, -1., 1.)
+        },
+    }
+}
+
+/// The transformed integrand f(x) dx/dt. Near the end of the finite interval x rounds to infinity, where a convergent integrand contributes nothing.
+fn towards_infinity(f: &mut impl FnMut(f64) -> Result<f64>, x: f64, dx: f64) -> Result<f64> {
+    if !x.is_finite() || !dx.is_finite() {
+        return Ok(0.);
+    }
+    let y = f(x)?;
+    Ok(if y == 0. { 0. } else { y * dx })
+}
+
+fn adaptive(f: &mut impl FnMut(f64) -> Result<f64>, a: f64, b: f64) -> Result<f64> {
+    let (integral, error) = kronrod(f, a, b)?;
+    let mut intervals = vec![(a, b, integral, error)];
+    loop {
+        let total: f64 = intervals.iter().map(|i| i.2).sum();
+        let error: f64 = intervals.iter().map(|i| i.3).sum();
+        if !total.is_finite() || !error.is_finite() {
+            return Err(CalcError::Domain("The integral diverges".to_owned()));
+        }
+        if error <= INTEGRAL_TOLERANCE * total.abs() || error == 0. {
+            return Ok(total);
+        }
+        let worst = (0..intervals.len()).max_by(|i, j| intervals[*i].3.total_cmp(&intervals[*j].3)).unwrap_or(0);
+        let (lo, hi, _, _) = intervals[worst];
+        let mid = 0.5 * (lo + hi);
+        // Stop when the intervals cannot be split any further
+        if intervals.len() >= MAX_INTERVALS || mid <= lo || mid >= hi {
+            if error <= INTEGRAL_ACCEPTABLE * total.abs() {
+                return Ok(total);
+            }
+            return Err(CalcError::Domain(format!("The integral did not converge (its estimated error is {:.1e}). It may diverge", error)));
+        }
+        let (left, left_error) = kronrod(f, lo, mid)?;
+        let (right, right_error) = kronrod(f, mid, hi)?;
+        intervals[worst] = (lo, mid, left, left_error);
+        intervals.push((mid, hi, right, right_error));
+    }
+}
+
+/// The factor by which the step of a numeric derivative shrinks between extrapolations
+const STEP_SHRINK: f64 = 1.4;
+/// The most steps one pass of a numeric derivative tries
+const DERIVATIVE_STEPS: usize = 10;
+/// The first step of a numeric derivative, relative to the larger of |x| and 1
+const FIRST_STEP: f64 = 0.1;
+/// Each pass of a numeric derivative starts from a step this many times smaller than the last
+const PASS_SHRINK: f64 = 10.;
+/// The most passes a numeric derivative makes
+const DERIVATIVE_PASSES: usize = 30;
+/// The most significant figures a numeric derivative is given to
+const DERIVATIVE_DIGITS: i32 = 13;
+/// The step, relative to the first step of a numeric derivative at x rather than at 1, at which the slopes on either side are compared
+const KINK_STEP: f64 = 1e-3;
+
+/// Whether f has a kink or a step at x. At a kink the slopes on either side differ by an amount which does not shrink with the step, where for a smooth function the difference is about f''(x) h. Across a step the slope grows as the step shrinks.
+fn kinked(f: &mut impl FnMut(f64) -> Result<f64>, x: f64, h: f64) -> Result<bool> {
+    let y = value(f, x)?;
+    // The difference of the one-sided slopes, the larger of them, and the central slope
+    let mut slopes = |h: f64| -> Result<(f64, f64, f64)> {
+        let (forward, backward) = ((value(f, x + h)? - y) / h, (y - value(f, x - h)?) / h);
+        Ok(((forward - backward).abs(), forward.abs().max(backward.abs()), 0.5 * (forward + backward).abs()))
+    };
+    let (wide_gap, slope, wide_central) = slopes(h)?;
+    let (narrow_gap, _, narrow_central) = slopes(h / 10.)?;
+    // Differences at the level of rounding error say nothing either way
+    let kink = wide_gap > 1e-6 * slope && narrow_gap > 0.5 * wide_gap;
+    let step = wide_central * h > 1e-10 * y.abs() && narrow_central > 5. * wide_central;
+    Ok(kink || step)
+}
+
+/// One pass of Ridders' method: central differences from the step h down, combined by Richardson extrapolation until its error estimate starts to grow. Gives the best estimate and its error.
+/// central gives the difference at a step and the rounding error in it, which the error estimate never goes below: differences of values like 1e10 + x can agree exactly and still be wrong.
+fn ridders(central: &mut impl FnMut(f64) -> Result<(f64, f64)>, mut h: f64) -> Result<(f64, f64)> {
+    // previous[j] is the estimate from the last step, extrapolated j times
+    let mut previous = vec![central(h)?.0];
+    let mut best = previous[0];
+    let mut error = f64::INFINITY;
+    for _ in 1..DERIVATIVE_STEPS {
+        h /= STEP_SHRINK;
+        let (slope, rounding) = central(h)?;
+        let mut row = vec![slope];
+        let mut factor = STEP_SHRINK * STEP_SHRINK;
+        for j in 1..=previous.len() {
+            // (row factor - previous) / (factor - 1), arranged not to overflow near the largest numbers
+            let next = row[j - 1] + (row[j - 1] - previous[j - 1]) / (factor - 1.);
+            factor *= STEP_SHRINK * STEP_SHRINK;
+            let change = (next - row[j - 1]).abs().max((next - previous[j - 1]).abs()).max(rounding);
+            if change <= error && next.is_finite() {
+                error = change;
+                best = next;
+            }
+            row.push(next);
+        }
+        let last = previous.len();
+        let diverging = (row[last] - previous[last - 1]).abs() >= 2. * error;
+        previous = row;
+        if diverging {
+            break;
+        }
+    }
+    Ok((best, error))
+}
+
+/// The derivative of f at x by Ridders' method, given to the significant figures its error estimate supports.
+/// The right first step depends on f: a large one averages out rounding in f, as in 1e10 + x, but must not reach past a pole or overflow, as 1/x at 0.001 and exp(x) at 700 would. So passes start from steps of 0.1 max(|x|, 1) down to about cbrt(ε) |x|, below which rounding swamps the differences, and the pass with the smallest error wins. Steps at which f is not defined or not finite are skipped.
+/// A kink or step at x, as abs(x) and sign(x) have at 0, is an error rather than the average of the slopes on either side.
+pub fn derivative(mut f: impl FnMut(f64) -> Result<f64>, x: f64) -> Result<f64> {
+    if value(&mut f, x)?.is_infinite() {
+        return Err(CalcError::Domain(format!("The derivative is not defined at {}, where the function is infinite", x)));
+    }
+    let near = if x == 0. { 1. } else { x.abs() };
+    if kinked(&mut f, x, KINK_STEP * FIRST_STEP * near)? {
+        return Err(CalcError::Domain(format!("The derivative is not defined at {}, where the function has a kink or a step", x)));
+    }
+    let mut central = |h: f64| -> Result<(f64, f64)> {
+        let (above, below) = (value(&mut f, x + h)?, value(&mut f, x - h)?);
+        let slope = (above - below) / (2. * h);
+        if !slope.is_finite() {
+            return Err(CalcError::Overflow(format!("The derivative is not finite near {}", x)));
+        }
+        Ok((slope, f64::EPSILON * (above.abs() / 2. + below.abs() / 2.) / h))
+    };
+    let smallest = f64::EPSILON.cbrt() * near;
+    let mut h = FIRST_STEP * x.abs().max(1.);
+    let mut found: Option<(f64, f64)> = None;
+    let mut failure = None;
+    for _ in 0..DERIVATIVE_PASSES {
+        match ridders(&mut central, h) {
+            Ok((slope, error)) if found.is_none_or(|(_, best)| error < best) => found = Some((slope, error)),
+            Ok(_) => {},
+            // Domain and overflow errors mean the step reached too far, but others are errors in the expression
+            Err(e @ (CalcError::Domain(_) | CalcError::Overflow(_))) => failure = Some(e),
+            Err(e) => return Err(e),
+        }
+        let settled = found.is_some_and(|(slope, error)| error <= slope.abs() * 10f64.powi(-DERIVATIVE_DIGITS));
+        h /= PASS_SHRINK;
+        if settled || h < smallest {
+            break;
+        }
+    }
+    let Some((slope, error)) = found.filter(|(_, error)| error.is_finite()) else {
+        return Err(failure.unwrap_or_else(|| CalcError::Overflow(format!("The derivative at {} is too large to estimate", x))));
+    };
+    // Keep only the digits the error leaves meaningful, so that a slope of 1e10 + x is 1 rather than 0.99999882. This also finds 12 rather than 11.999999999999986, and 0 for a slope smaller than its error.
+    let digits = (slope.abs() / error).log10().floor().min(DERIVATIVE_DIGITS as f64) as i32;
+    if digits < 1 {
+        return Ok(0.);
+    }
+    let digits = digits as usize;
+    Ok(format!("{:.*e}", digits - 1, slope).parse().unwrap_or(slope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Find a root from a guess, as solve(f, x, guess) does
+    fn root(f: impl Fn(f64) -> f64, guess: f64) -> f64 {
+        let (a, b) = bracket(|x| Ok(f(x)), guess).unwrap();
+        if a == b { a } else { solve(|x| Ok(f(x)), a, b).unwrap() }
+    }
+
+    #[test]
+    fn finds_roots_across_zero_from_a_guess() {
+        assert_eq!(root(|x| x + 5., 1.), -5.);
+        assert_eq!(root(|x| x, 1.), 0.);
+        assert_eq!(root(|x| x - 3., 0.), 3.);
+        assert!((root(|x| x * x - 2., 1.) - 2f64.sqrt()).abs() < 1e-15);
+    }
+
+    #[test]
+    fn integrates_to_infinite_limits() {
+        let pi = std::f64::consts::PI;
+        let gaussian = integrate(|x| Ok((-x * x).exp()), f64::NEG_INFINITY, f64::INFINITY).unwrap();
+        assert!((gaussian - pi.sqrt()).abs() < 1e-9);
+        let lorentzian = integrate(|x| Ok(1. / (1. + x * x)), 0., f64::INFINITY).unwrap();
+        assert!((lorentzian - pi / 2.).abs() < 1e-9);
+        // Far from 1 in CGS units, as a length in cm might be
+        let scaled = integrate(|x| Ok((-x / 1e18).exp()), 0., f64::INFINITY).unwrap();
+        assert!((scaled / 1e18 - 1.).abs() < 1e-9);
+        assert!(integrate(|x| Ok(1. / x), 1., f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn differentiates_near_a_kink_but_not_at_it() {
+        assert_eq!(derivative(|x| Ok(x.abs()), 1e-3).unwrap(), 1.);
+        assert_eq!(derivative(|x| Ok(x.abs()), -2.).unwrap(), -1.);
+        assert!(derivative(|x| Ok(x.abs()), 0.).is_err());
+        assert!(derivative(|x| Ok(x.signum()), 0.).is_err());
+        // Smooth functions with a zero slope or curvature are not mistaken for kinks
+        assert_eq!(derivative(|x| Ok(x.cos()), 0.).unwrap(), 0.);
+        assert!(derivative(|x| Ok(x * x * x), 0.).unwrap().abs() < 1e-15);
+        assert!((derivative(|x| Ok(x.exp()), 1.).unwrap() - std::f64::consts::E).abs() < 1e-11);
+    }
+
+    #[test]
+    fn differentiates_to_the_digits_the_error_supports() {
+        // Rounding in the large constant leaves only a few meaningful digits
+        assert_eq!(derivative(|x| Ok(1e10 + x), 1.).unwrap(), 1.);
+        assert_eq!(derivative(|x| Ok(x * x * x + 1e8), 0.37).unwrap(), 0.4107);
+        // Steps which overflow or reach past a pole are skipped
+        let slope = derivative(|x| Ok(x.exp()), 700.).unwrap();
+        assert!((slope / 700f64.exp() - 1.).abs() < 1e-9);
+        assert_eq!(derivative(|x| Ok(1. / x), 1e-3).unwrap(), -1e6);
+        assert!(derivative(|x| Ok(1. / x), 0.).is_err());
+    }
+}
@@ -1,95 +1,660 @@
-use anyhow::{Result, anyhow};
+use crate::defaults::SCALES;
+use crate::error::{CalcError, Result};
+use crate::convert::{Convert, Equivalency, Target};
+use crate::derivative::Derivative;
+use crate::dimensions::DimSolve;
+use crate::expr::{Assertion, BinaryOp, Expr, PrintItem, Statement, Tolerance};
+use crate::integral::Integral;
+use crate::plot::Plot;
+use crate::scaling::Scaling;
+use crate::solve::Solve;
+use crate::table::{Spacing, Sweep};
+use crate::lexer::{tokenize, Token, TokenKind};
 use crate::number::Number;
-use crate::instruction::{Operator, Instruction};
-
-
-pub fn parse(text: &str) -> Result<Instruction> {
-    let text = if text.ends_with("\n") {
-        text.to_owned()
-    } else {
-        format!("{}\n", text)
-    };
-    let mut output = Instruction::head();
-    let mut cursor = output.get_first_working_child();
-
-    let mut current_string = "".to_owned();
-    for c in text.chars() {
-        let mut is_string = false;
-        is_string |= 'a' <= c && c <= 'z';
-        is_string |= 'A' <= c && c <= 'Z';
-        is_string |= '0' <= c && c <= '9';
-        is_string |= c == '_' || c == '.';
-        if c == '-' {
-            // Only include minus signs if the last symbol was e and this is for sure a number
-            match current_string.chars().last() {
-                Some('e') | Some('E') =>   if let Some(first_char) = current_string.chars().nth(0) {
-                        if '0' <= first_char && first_char <= '9' {
-                            is_string = true;
-                        }
-                    },
-                _ => ()
+use crate::unit::Unit;
+
+/// Words with a meaning of their own at the start of a statement or inside one, rather than names to look up
+pub const KEYWORDS: &[&str] = &["print", "assert", "dim", "within", "table", "lin", "log", "solve", "integrate", "diff", "scaling", "dimsolve", "plot", "logy", "loglog", "in", "with"];
+
+/// Words which end an expression instead of being multiplied into it
+const STOP_WORDS: &[&str] = &["within", "in", "with"];
+
+/// Parse a line into a statement. Parsing does not look anything up, so it has no side effects and can be run on partial input.
+pub fn parse(text: &str) -> Result<Statement> {
+    let mut parser = Parser { text, tokens: tokenize(text), pos: 0 };
+    let statement = parser.statement()?;
+    match parser.peek() {
+        None => Ok(statement),
+        Some(TokenKind::RParen) => Err(CalcError::Parse("Too many )".to_owned())),
+        Some(t) => Err(unexpected(t)),
+    }
+}
+
+fn unexpected(token: &TokenKind) -> CalcError {
+    match token {
+        TokenKind::Unknown(c) => CalcError::Parse(format!("Unrecognized character {}", c)),
+        t => CalcError::Parse(format!("Unexpected {}", describe(t))),
+    }
+}
+
+fn describe(token: &TokenKind) -> String {
+    match token {
+        TokenKind::Number(q) => format!("number {}", q),
+        TokenKind::Name(name) => format!("name {}", name),
+        TokenKind::Str(text) => format!("string \"{}\"", text),
+        TokenKind::Plus => "+".to_owned(),
+        TokenKind::Minus => "-".to_owned(),
+        TokenKind::Star => "*".to_owned(),
+        TokenKind::Slash => "/".to_owned(),
+        TokenKind::Caret => "^".to_owned(),
+        TokenKind::LParen => "(".to_owned(),
+        TokenKind::RParen => ")".to_owned(),
+        TokenKind::LBracket => "[".to_owned(),
+        TokenKind::RBracket => "]".to_owned(),
+        TokenKind::Comma => ",".to_owned(),
+        TokenKind::DotDot => "..".to_owned(),
+        TokenKind::Equals => "=".to_owned(),
+        TokenKind::EqualsEquals => "==".to_owned(),
+        TokenKind::Percent => "%".to_owned(),
+        TokenKind::Unknown(c) => c.to_string(),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.pos + offset).map(|t| &t.kind)
+    }
+
+    fn next(&mut self) -> Option<TokenKind> {
+        let token = self.tokens.get(self.pos).map(|t| t.kind.clone());
+        self.pos += 1;
+        token
+    }
+
+    /// Consume the next token if it is the given one
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == Some(kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::Equals)) = (self.peek(), self.peek_at(1)) {
+            let name = name.clone();
+            self.pos += 2;
+            return Ok(Statement::Assign(name, self.expr()?));
+        }
+        if let Some(TokenKind::Name(name)) = self.peek() && name == "print" {
+            self.pos += 1;
+            return self.print();
+        }
+        if let Some(TokenKind::Name(name)) = self.peek() && name == "assert" {
+            self.pos += 1;
+            return self.assertion();
+        }
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1)) && name == "table" {
+            self.pos += 2;
+            let (expr, label) = self.labelled_expr()?;
+            self.comma()?;
+            let sweep = self.sweep()?;
+            self.close()?;
+            return Ok(Statement::Table(expr, label, sweep));
+        }
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1)) && name == "plot" {
+            self.pos += 2;
+            return self.plot();
+        }
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1)) && name == "dimsolve" {
+            self.pos += 2;
+            return self.dimsolve();
+        }
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1)) && name == "scaling" {
+            self.pos += 2;
+            return self.scaling();
+        }
+        let expr = self.expr()?;
+        if let Some(TokenKind::Name(name)) = self.peek() && name == "in" {
+            self.pos += 1;
+            let target = self.target()?;
+            let equivalencies = self.equivalencies()?;
+            return Ok(Statement::Convert(Convert { expr, target, equivalencies }));
+        }
+        Ok(Statement::Expr(expr))
+    }
+
+    /// What to convert to after `in`: a logarithmic scale on its own, or units written as an expression
+    fn target(&mut self) -> Result<Target> {
+        let alone = match self.peek_at(1) {
+            None => true,
+            Some(TokenKind::Name(next)) => next == "with",
+            _ => false,
+        };
+        if let Some(TokenKind::Name(name)) = self.peek() && alone && SCALES.contains_key(name.as_str()) {
+            let name = name.clone();
+            self.pos += 1;
+            return Ok(Target::Scale(name));
+        }
+        Ok(Target::Units(self.expr()?))
+    }
+
+    /// The equivalencies after `with`, separated by commas, such as `with spectral, spectral_density(5000 angstrom)`
+    fn equivalencies(&mut self) -> Result<Vec<Equivalency>> {
+        let mut equivalencies = Vec::new();
+        if !matches!(self.peek(), Some(TokenKind::Name(name)) if name == "with") {
+            return Ok(equivalencies);
+        }
+        self.pos += 1;
+        loop {
+            let Some(TokenKind::Name(name)) = self.peek() else {
+                return Err(CalcError::Parse("Expected the name of an equivalency after with, such as spectral".to_owned()));
+            };
+            let name = name.clone();
+            self.pos += 1;
+            let at = if self.eat(&TokenKind::LParen) {
+                let at = self.expr()?;
+                self.close()?;
+                Some(at)
+            } else {
+                None
+            };
+            equivalencies.push(Equivalency { name, at });
+            if !self.eat(&TokenKind::Comma) {
+                return Ok(equivalencies);
             }
         }
-        if is_string {
-            current_string.push(c);
-        } else {
-            if !current_string.is_empty() {
-                // Commit the string
-                if let Operator::Working = cursor.operator {
+    }
+
+    /// The arguments of scaling, after the opening parenthesis: an expression and at least one `name = reference`
+    fn scaling(&mut self) -> Result<Statement> {
+        let expr = self.expr()?;
+        let mut references = Vec::new();
+        while self.eat(&TokenKind::Comma) {
+            let name = self.variable()?;
+            match self.next() {
+                Some(TokenKind::Equals) => {},
+                Some(t) => return Err(CalcError::Parse(format!("Expected = after {}, not {}", name, describe(&t)))),
+                None => return Err(CalcError::Parse(format!("Expected = after {}", name))),
+            }
+            references.push((name, self.expr()?));
+        }
+        if references.is_empty() {
+            return Err(CalcError::Parse("Give scaling at least one reference value, as in scaling(2 GN M / c^2, M = 1 msun)".to_owned()));
+        }
+        self.close()?;
+        Ok(Statement::Scaling(Scaling { expr, references }))
+    }
+
+    /// The name of a dimension, which may be several words such as number density
+    fn dimension(&mut self) -> Option<String> {
+        let mut words = Vec::new();
+        while let Some(TokenKind::Name(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        if words.is_empty() { None } else { Some(words.join(" ")) }
+    }
+
+    /// The arguments of dimsolve, after the opening parenthesis: a dimension, then quantities which are either expressions or `name = value`
+    fn dimsolve(&mut self) -> Result<Statement> {
+        let Some(target) = self.dimension() else {
+            return Err(CalcError::Parse("Expected a dimension such as length, as in dimsolve(length, GN, c, hbar)".to_owned()));
+        };
+        let mut quantities = Vec::new();
+        while self.eat(&TokenKind::Comma) {
+            if let (Some(TokenKind::Name(name)), Some(TokenKind::Equals)) = (self.peek(), self.peek_at(1)) {
+                let name = name.clone();
+                self.pos += 2;
+                quantities.push((name, self.expr()?));
+            } else {
+                quantities.push(self.labelled_expr().map(|(expr, label)| (label, expr))?);
+            }
+        }
+        if quantities.is_empty() {
+            return Err(CalcError::Parse("Give dimsolve the quantities to combine, as in dimsolve(length, GN, c, hbar)".to_owned()));
+        }
+        self.close()?;
+        Ok(Statement::DimSolve(DimSolve { target, quantities }))
+    }
+
+    /// The arguments of plot, after the opening parenthesis: one or more expressions, then a sweep whose spacing may also be `logy` or `loglog`
+    fn plot(&mut self) -> Result<Statement> {
+        // The variable follows the last comma before the .. of the range
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        for (i, token) in self.tokens.iter().enumerate().skip(self.pos) {
+            match token.kind {
+                TokenKind::LParen | TokenKind::LBracket => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket if depth == 0 => break,
+                TokenKind::RParen | TokenKind::RBracket => depth -= 1,
+                TokenKind::Comma if depth == 0 => commas.push(i),
+                TokenKind::DotDot if depth == 0 => break,
+                _ => {},
+            }
+        }
+        let Some(&before_variable) = commas.len().checked_sub(2).and_then(|i| commas.get(i)) else {
+            return Err(CalcError::Parse("Expected plot(expression, x, a .. b)".to_owned()));
+        };
+        let mut curves = Vec::new();
+        loop {
+            curves.push(self.labelled_expr()?);
+            if self.pos >= before_variable {
+                break;
+            }
+            self.comma()?;
+        }
+        self.comma()?;
+
+        let (mut sweep, axes) = self.sweep_with(&["logy", "loglog"])?;
+        if axes.as_deref() == Some("loglog") {
+            sweep.spacing = Spacing::Log;
+        }
+        let log_y = axes.is_some();
+        self.close()?;
+        Ok(Statement::Plot(Plot { curves, sweep, log_y }))
+    }
+
+    /// The strings and expressions of a print statement, optionally separated by commas
+    fn print(&mut self) -> Result<Statement> {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                TokenKind::Str(text) => {
+                    items.push(PrintItem::Text(text.clone()));
+                    self.pos += 1;
+                },
+                TokenKind::Comma => self.pos += 1,
+                _ => items.push(PrintItem::Expr(self.expr()?)),
+            }
+        }
+        Ok(Statement::Print(items))
+    }
+
+    /// `assert a == b`, optionally followed by `within 1%` or `within 1 km`, or `assert dim(a) == energy`
+    fn assertion(&mut self) -> Result<Statement> {
+        if let (Some(TokenKind::Name(name)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1)) && name == "dim" {
+            self.pos += 2;
+            let value = self.expr()?;
+            self.close()?;
+            self.equals_equals()?;
+            let Some(dimension) = self.dimension() else {
+                return Err(CalcError::Parse("Expected a dimension such as energy after ==".to_owned()));
+            };
+            return Ok(Statement::Assert(Assertion::Dimension(value, dimension)));
+        }
+
+        let lhs = self.expr()?;
+        self.equals_equals()?;
+        let rhs = self.expr()?;
+        let tolerance = match self.peek() {
+            Some(TokenKind::Name(name)) if name == "within" => {
+                self.pos += 1;
+                let amount = self.expr()?;
+                if self.eat(&TokenKind::Percent) {
+                    Some(Tolerance::Percent(amount))
                 } else {
-                    // If there was a number before, assume the operation in between is multiplication
-                    let child = Instruction::insert_in_parent(cursor, Operator::Mul);
-                    cursor = child.get_first_working_child();
+                    Some(Tolerance::Absolute(amount))
                 }
-                match Number::parse(&current_string) {
-                    Ok(number) => cursor.operator = Operator::Number(number),
-                    Err(()) => cursor.operator = Operator::Func(current_string),
+            },
+            _ => None,
+        };
+        Ok(Statement::Assert(Assertion::Equal(lhs, rhs, tolerance)))
+    }
+
+    fn equals_equals(&mut self) -> Result<()> {
+        match self.next() {
+            Some(TokenKind::EqualsEquals) => Ok(()),
+            Some(t) => Err(CalcError::Parse(format!("Expected == in an assertion, not {}", describe(&t)))),
+            None => Err(CalcError::Parse("Expected == in an assertion".to_owned())),
+        }
+    }
+
+    /// An expression and the text it was parsed from
+    fn labelled_expr(&mut self) -> Result<(Expr, String)> {
+        let start = self.tokens.get(self.pos).map_or(self.text.len(), |t| t.start);
+        let expr = self.expr()?;
+        let end = self.tokens.get(self.pos - 1).map_or(self.text.len(), |t| t.end);
+        Ok((expr, self.text[start..end.max(start)].to_owned()))
+    }
+
+    /// `x, a .. b`, optionally followed by the number of values and `lin` or `log`
+    fn sweep(&mut self) -> Result<Sweep> {
+        Ok(self.sweep_with(&[])?.0)
+    }
+
+    /// A sweep whose last argument may also be one of the given words instead of `lin` or `log`. Returns the word, if it is one of them.
+    fn sweep_with(&mut self, words: &[&str]) -> Result<(Sweep, Option<String>)> {
+        let variable = self.variable()?;
+        self.comma()?;
+        let from = self.expr()?;
+        match self.next() {
+            Some(TokenKind::DotDot) => (),
+            Some(t) => return Err(CalcError::Parse(format!("Expected .. between the ends of a range, not {}", describe(&t)))),
+            None => return Err(CalcError::Parse("Expected .. between the ends of a range".to_owned())),
+        }
+        let to = self.expr()?;
+        let mut sweep = Sweep { variable, from, to, count: None, spacing: Spacing::Linear };
+        let mut word = None;
+        if self.eat(&TokenKind::Comma) {
+            word = self.spacing(words);
+            if word.is_none() {
+                sweep.count = Some(self.expr()?);
+                if self.eat(&TokenKind::Comma) {
+                    let mut expected: Vec<&str> = ["lin", "log"].iter().chain(words).copied().collect();
+                    let last = expected.pop().unwrap_or_default();
+                    word = Some(self.spacing(words).ok_or_else(|| CalcError::Parse(format!("Expected {} or {}", expected.join(", "), last)))?);
                 }
-                current_string = "".to_owned();
             }
+        }
+        match word.as_deref() {
+            None => Ok((sweep, None)),
+            Some(word) => {
+                self.pos += 1;
+                match word {
+                    "lin" | "linear" => Ok((sweep, None)),
+                    "log" => {
+                        sweep.spacing = Spacing::Log;
+                        Ok((sweep, None))
+                    },
+                    word => Ok((sweep, Some(word.to_owned()))),
+                }
+            },
+        }
+    }
+
+    /// The spacing word, or one of the other given words, if the next token is one and ends the arguments
+    fn spacing(&self, words: &[&str]) -> Option<String> {
+        match self.peek() {
+            Some(TokenKind::Name(name)) if ["lin", "linear", "log"].contains(&name.as_str()) || words.contains(&name.as_str()) => {
+                matches!(self.peek_at(1), Some(TokenKind::RParen) | None).then(|| name.clone())
+            },
+            _ => None,
+        }
+    }
+
+    /// The name of the variable a form like table or solve varies
+    fn variable(&mut self) -> Result<String> {
+        match self.next() {
+            Some(TokenKind::Name(name)) => Ok(name),
+            Some(t) => Err(CalcError::Parse(format!("Expected the name of a variable, not {}", describe(&t)))),
+            None => Err(CalcError::Parse("Expected the name of a variable".to_owned())),
+        }
+    }
+
+    fn comma(&mut self) -> Result<()> {
+        match self.next() {
+            Some(TokenKind::Comma) => Ok(()),
+            Some(t) => Err(CalcError::Parse(format!("Expected , not {}", describe(&t)))),
+            None => Err(CalcError::Parse("Missing )".to_owned())),
+        }
+    }
+
+    /// Sums and differences
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Plus) => BinaryOp::Add,
+                Some(TokenKind::Minus) => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    /// Products, quotients and remainders, which group from the left
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.juxtaposed()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Star) => {
+                    self.pos += 1;
+                    BinaryOp::Mul
+                },
+                Some(TokenKind::Slash) => {
+                    self.pos += 1;
+                    BinaryOp::Div
+                },
+                // A % with nothing after it is a percentage, as in within 1%
+                Some(TokenKind::Percent) if matches!(
+                    self.peek_at(1),
+                    Some(TokenKind::Number(_) | TokenKind::Name(_) | TokenKind::LParen | TokenKind::LBracket | TokenKind::Minus)
+                ) => {
+                    self.pos += 1;
+                    BinaryOp::Mod
+                },
+                _ => return Ok(lhs),
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.juxtaposed()?));
+        }
+    }
+
+    /// Operands multiplied by writing them side by side, as in `3 km`. This binds more tightly than * and /, so 3 km / 2 hr is (3 km) / (2 hr).
+    fn juxtaposed(&mut self) -> Result<Expr> {
+        let mut lhs = self.level()?;
+        loop {
+            match self.peek() {
+                Some(TokenKind::Name(name)) if STOP_WORDS.contains(&name.as_str()) => return Ok(lhs),
+                Some(TokenKind::Number(_) | TokenKind::Name(_) | TokenKind::LParen | TokenKind::LBracket) => {
+                    lhs = Expr::Binary(BinaryOp::Mul, Box::new(lhs), Box::new(self.level()?));
+                },
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    /// An operand followed by the name of a logarithmic scale, as in `20 ABmag`, is a level on that scale
+    fn level(&mut self) -> Result<Expr> {
+        let operand = self.unary()?;
+        if let Some(TokenKind::Name(name)) = self.peek() && SCALES.contains_key(name.as_str()) {
+            let name = name.clone();
+            self.pos += 1;
+            return Ok(Expr::Level(Box::new(operand), name));
+        }
+        Ok(operand)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat(&TokenKind::Minus) {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat(&TokenKind::Plus) {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    /// Exponents, which group from the right
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if self.eat(&TokenKind::Caret) {
+            return Ok(Expr::Binary(BinaryOp::Expon, Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
 
-            if c == ' ' {continue;}
-
-            // Process the operation
-            if c == '*' {
-                let child = Instruction::insert_in_parent(cursor, Operator::Mul);
-                cursor = child.get_first_working_child();
-            } else if c == '/' {
-                let child = Instruction::insert_in_parent(cursor, Operator::Div);
-                cursor = child.get_first_working_child();
-            } else if c == '+' {
-                let child = Instruction::insert_in_parent(cursor, Operator::Add);
-                cursor = child.get_first_working_child();
-            } else if c == '-' {
-                // TODO handle negation
-                let child = if let Operator::Working = cursor.operator {
-                    Instruction::insert_in_parent(cursor, Operator::Neg)
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(TokenKind::Number(q)) => Ok(Expr::Number(Number::new(q, Unit::one()))),
+            Some(TokenKind::Name(name)) if name == "solve" && self.eat(&TokenKind::LParen) => self.solve(),
+            Some(TokenKind::Name(name)) if name == "integrate" && self.eat(&TokenKind::LParen) => self.integral(),
+            Some(TokenKind::Name(name)) if name == "diff" && self.eat(&TokenKind::LParen) => self.derivative(),
+            Some(TokenKind::Name(name)) if name == "d" && let Some(variable) = self.by_variable() => {
+                let expr = self.expr()?;
+                self.close()?;
+                Ok(Expr::Derivative(Box::new(Derivative { expr, variable, at: None })))
+            },
+            Some(TokenKind::Name(name)) if SCALES.contains_key(name.as_str()) => {
+                Err(CalcError::Parse(format!("{} is a logarithmic scale. Put the level before it, as in 20 {}", name, name)))
+            },
+            Some(TokenKind::Name(name)) => {
+                if self.eat(&TokenKind::LParen) {
+                    let args = self.arguments()?;
+                    Ok(Expr::Call(name, args))
                 } else {
-                    Instruction::insert_in_parent(cursor, Operator::Sub)
-                };
-                cursor = child.get_first_working_child();
-            } else if c == '^' {
-                let child = Instruction::insert_in_parent(cursor, Operator::Expon);
-                cursor = child.get_first_working_child();
-            } else if c == '\n' {
-                break;
-            } else if c == ')' {
-                cursor = cursor.close_parentheses()?;
-            } else if c == '(' {
-                if let Operator::Func(_) = cursor.operator {
-                    cursor = cursor.get_first_working_child();
-                }
-                cursor.operator = Operator::Parentheses;
-                cursor = cursor.get_first_working_child();
-            } else if c == ',' {
-                if let Operator::Func(_) = cursor.get_parent().operator {} else {
-                    return Err(anyhow!("You cannot use , except in a function"))
+                    Ok(Expr::Name(name))
                 }
-                cursor = cursor.get_next_child();
-            } else {
-                return Err(anyhow!("Unrecognized character {}", c))
+            },
+            Some(TokenKind::LParen) => {
+                let inner = self.expr()?;
+                self.close()?;
+                Ok(inner)
+            },
+            Some(TokenKind::LBracket) => self.list(),
+            Some(t) => Err(unexpected(&t)),
+            None => Err(CalcError::Parse("Unexpected end of input".to_owned())),
+        }
+    }
+
+    /// The arguments of solve, after the opening parenthesis
+    fn solve(&mut self) -> Result<Expr> {
+        let lhs = self.expr()?;
+        let rhs = if self.eat(&TokenKind::EqualsEquals) { Some(self.expr()?) } else { None };
+        self.comma()?;
+        let variable = self.variable()?;
+        self.comma()?;
+        let start = self.expr()?;
+        let end = if self.eat(&TokenKind::DotDot) { Some(self.expr()?) } else { None };
+        self.close()?;
+        Ok(Expr::Solve(Box::new(Solve { lhs, rhs, variable, start, end })))
+    }
+
+    /// The arguments of integrate, after the opening parenthesis
+    fn integral(&mut self) -> Result<Expr> {
+        let integrand = self.expr()?;
+        self.comma()?;
+        let variable = self.variable()?;
+        self.comma()?;
+        let from = self.expr()?;
+        self.comma()?;
+        let to = self.expr()?;
+        self.close()?;
+        Ok(Expr::Integral(Box::new(Integral { integrand, variable, from, to })))
+    }
+
+    /// The arguments of diff, after the opening parenthesis. Without a point the derivative is symbolic.
+    fn derivative(&mut self) -> Result<Expr> {
+        let expr = self.expr()?;
+        self.comma()?;
+        let variable = self.variable()?;
+        let at = if self.eat(&TokenKind::Comma) { Some(self.expr()?) } else { None };
+        self.close()?;
+        Ok(Expr::Derivative(Box::new(Derivative { expr, variable, at })))
+    }
+
+    /// The `/dx(` of `d/dx(expr)`, after the d, consumed only if it is all there. Returns the variable.
+    fn by_variable(&mut self) -> Option<String> {
+        let (Some(TokenKind::Slash), Some(TokenKind::Name(dx)), Some(TokenKind::LParen)) = (self.peek(), self.peek_at(1), self.peek_at(2)) else {
+            return None;
+        };
+        let variable = dx.strip_prefix('d').filter(|x| !x.is_empty())?.to_owned();
+        self.pos += 3;
+        Some(variable)
+    }
+
+    /// The comma-separated arguments of a function call, after the opening parenthesis
+    fn arguments(&mut self) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.eat(&TokenKind::RParen) {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(&TokenKind::Comma) {
+                continue;
+            }
+            self.close()?;
+            return Ok(args);
+        }
+    }
+
+    /// The comma-separated elements of a list, after the opening bracket
+    fn list(&mut self) -> Result<Expr> {
+        let mut items = Vec::new();
+        if self.eat(&TokenKind::RBracket) {
+            return Ok(Expr::List(items));
+        }
+        loop {
+            items.push(self.expr()?);
+            if self.eat(&TokenKind::Comma) {
+                continue;
             }
+            return match self.next() {
+                Some(TokenKind::RBracket) => Ok(Expr::List(items)),
+                Some(t) => Err(unexpected(&t)),
+                None => Err(CalcError::Parse("Missing ]".to_owned())),
+            };
+        }
+    }
+
+    fn close(&mut self) -> Result<()> {
+        match self.next() {
+            Some(TokenKind::RParen) => Ok(()),
+            Some(t) => Err(unexpected(&t)),
+            None => Err(CalcError::Parse("Missing )".to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(text: &str) -> Expr {
+        match parse(text) {
+            Ok(Statement::Expr(expr)) => expr,
+            other => panic!("{} parsed as {:?}", text, other),
         }
     }
-    Ok(output)
+
+    #[test]
+    fn juxtaposition_binds_more_tightly_than_division() {
+        assert_eq!(expr("3 km / 2 hr"), expr("(3 km) / (2 hr)"));
+        assert_eq!(expr("1 / 2 km"), expr("1 / (2 km)"));
+        assert_eq!(expr("GN M / c^2 R"), expr("(GN M) / ((c^2) R)"));
+        assert_eq!(expr("2 * 3 km"), expr("2 * (3 km)"));
+        // % is parsed like /, so units after the divisor belong to it
+        assert_eq!(expr("5 km % 2 km"), expr("(5 km) % (2 km)"));
+        assert_eq!(expr("10 % 3 m"), expr("10 % (3 m)"));
+        assert_eq!(expr("a / b % c"), expr("(a / b) % c"));
+    }
+
+    #[test]
+    fn operators_group_from_the_left_except_powers() {
+        assert_eq!(expr("8 / 2 / 2"), expr("(8 / 2) / 2"));
+        assert_eq!(expr("6 - 2 + 1"), expr("(6 - 2) + 1"));
+        assert_eq!(expr("7 % 4 % 2"), expr("(7 % 4) % 2"));
+        assert_eq!(expr("2^3^2"), expr("2^(3^2)"));
+        assert_eq!(expr("-2^2"), expr("-(2^2)"));
+        assert_eq!(expr("1 + 2 * 3"), expr("1 + (2 * 3)"));
+    }
+
+    #[test]
+    fn printed_expressions_parse_back_the_same() {
+        for text in ["(a / b) c", "a / (b c)", "a / b / c", "a / (b / c)", "a - (b - c)", "a (-b)", "-a b", "-(a % b)", "(-a) % b", "a^(-b)", "(a b)^2", "-(20 ABmag)", "(-20) ABmag", "3 km · x / y", "x (20 dex)"] {
+            let parsed = expr(text);
+            assert_eq!(expr(&parsed.to_string()), parsed, "{} was printed as {}", text, parsed);
+        }
+    }
+
+    #[test]
+    fn products_are_not_printed_as_calls() {
+        assert_eq!(expr("x * (1 - x)").to_string(), "(1 - x) x");
+        assert_eq!(expr("2 x * (1 - x)^2").to_string(), "(1 - x)^2 2 x");
+        assert_eq!(expr("(y + 1) x * (1 - x)").to_string(), "(1 - x) (y + 1) x");
+        assert_eq!(expr("(p + q) x * ((r + s) y)").to_string(), "((p + q) x · (r + s) y)");
+    }
 }
@@ -0,0 +1,332 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::Expr, format::{Format, Notation, Precision}, number::Number, table::{Column, Spacing, Sweep, Table}, unit::Unit};
+
+/// The size of a terminal plot in characters, each of which holds 2 × 4 braille dots
+const WIDTH: usize = 60;
+const HEIGHT: usize = 15;
+/// The size of an SVG plot in pixels, and the margin around its axes
+const SVG_WIDTH: f64 = 640.;
+const SVG_HEIGHT: f64 = 400.;
+const SVG_MARGIN: f64 = 70.;
+/// The colours of successive curves, as ANSI codes and SVG names
+const COLOURS: &[(&str, &str)] = &[("34", "blue"), ("31", "red"), ("32", "green"), ("35", "magenta"), ("33", "orange"), ("36", "cyan")];
+
+/// Curves to plot: the first column of the table is the variable, and each other column is a curve with the same unit
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chart {
+    pub table: Table,
+    pub log_x: bool,
+    pub log_y: bool,
+}
+
+/// The fraction of the range of an axis within which a label is taken to be zero
+const LABEL_ZERO: f64 = 1e-3;
+
+/// One axis: the range of values it shows, and whether it is logarithmic
+struct Axis {
+    min: f64,
+    max: f64,
+    log: bool,
+}
+
+impl Axis {
+    /// The axis which fits the finite values, which must be positive on a log axis
+    fn fit<'a>(values: impl Iterator<Item = &'a f64>, log: bool) -> Self {
+        let (min, max) = values
+            .filter(|q| q.is_finite() && (!log || **q > 0.))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), q| (min.min(*q), max.max(*q)));
+        let (min, max) = match (min, max) {
+            (min, max) if min > max => if log { (1., 10.) } else { (0., 1.) },
+            (min, max) if min == max => if log { (min / 2., max * 2.) } else { (min - min.abs().max(1.) / 2., max + max.abs().max(1.) / 2.) },
+            range => range,
+        };
+        Self { min, max, log }
+    }
+
+    /// Where a value falls on the axis, from 0 at the minimum to 1 at the maximum, or None if it cannot be shown
+    fn position(&self, q: f64) -> Option<f64> {
+        if !q.is_finite() || (self.log && q <= 0.) {
+            return None;
+        }
+        Some(if self.log {
+            (q / self.min).ln() / (self.max / self.min).ln()
+        } else {
+            (q - self.min) / (self.max - self.min)
+        })
+    }
+
+    /// The value at a position on the axis
+    fn value(&self, t: f64) -> f64 {
+        if self.log {
+            self.min * (self.max / self.min).powf(t)
+        } else {
+            self.min + (self.max - self.min) * t
+        }
+    }
+
+    /// The value to label a position on the axis with. A value which is zero but for rounding, relative to the range of a linear axis, is labelled 0 rather than 1.75e-5.
+    fn label(&self, t: f64) -> f64 {
+        let q = self.value(t);
+        if !self.log && q.abs() < LABEL_ZERO * (self.max - self.min) {
+            0.
+        } else {
+            q
+        }
+    }
+}
+
+/// Numbers on the axes are short unless the format asks for digits
+fn tick_format(format: &Format) -> Format {
+    match format.precision {
+        Some(_) => *format,
+        None => Format { notation: Notation::Auto, precision: Some(Precision::Significant(3)) },
+    }
+}
+
+impl Chart {
+    fn x(&self) -> &Column {
+        &self.table.columns[0]
+    }
+
+    fn curves(&self) -> &[Column] {
+        &self.table.columns[1..]
+    }
+
+    fn axes(&self) -> (Axis, Axis) {
+        (
+            Axis::fit(self.x().values.iter(), self.log_x),
+            Axis::fit(self.curves().iter().flat_map(|c| &c.values), self.log_y),
+        )
+    }
+
+    /// The points of a curve as positions on the axes. A value which cannot be shown breaks the curve.
+    fn points(&self, curve: &Column, x_axis: &Axis, y_axis: &Axis) -> Vec<Option<(f64, f64)>> {
+        self.x().values.iter().zip(&curve.values)
+            .map(|(x, y)| Some((x_axis.position(*x)?, y_axis.position(*y)?)))
+            .collect()
+    }
+
+    /// The label of the y axis: the curve's text if there is one curve, with the unit they share
+    fn y_label(&self, format: &Format) -> String {
+        let curves = self.curves();
+        let name = if curves.len() == 1 { curves[0].name.clone() } else { "y".to_owned() };
+        Column { name, unit: curves[0].unit, values: Vec::new() }.header(format)
+    }
+
+    /// The plot drawn with braille characters, with the axes labelled in the units of the values. Each curve has its own colour if colour is set, and a legend if there is more than one.
+    pub fn render(&self, format: &Format, colour: bool) -> String {
+        let (x_axis, y_axis) = self.axes();
+        let ticks = tick_format(format);
+        // The dots set in each character, and the curve drawn there last
+        let mut cells = vec![vec![(0u8, 0usize); WIDTH]; HEIGHT];
+        let (dots_x, dots_y) = (WIDTH * 2 - 1, HEIGHT * 4 - 1);
+        for (index, curve) in self.curves().iter().enumerate() {
+            let dots: Vec<Option<(i64, i64)>> = self.points(curve, &x_axis, &y_axis).into_iter()
+                .map(|p| p.map(|(x, y)| ((x * dots_x as f64).round() as i64, ((1. - y) * dots_y as f64).round() as i64)))
+                .collect();
+            let mut set = |x: i64, y: i64| {
+                if (0..=dots_x as i64).contains(&x) && (0..=dots_y as i64).contains(&y) {
+                    let (x, y) = (x as usize, y as usize);
+                    let cell = &mut cells[y / 4][x / 2];
+                    cell.0 |= braille_bit(x % 2, y % 4);
+                    cell.1 = index;
+                }
+            };
+            for pair in dots.windows(2) {
+                match (pair[0], pair[1]) {
+                    (Some(a), Some(b)) => line(a, b, &mut set),
+                    (Some((x, y)), None) => set(x, y),
+                    _ => {},
+                }
+            }
+            if let Some(Some((x, y))) = dots.last() {
+                set(*x, *y);
+            }
+        }
+
+        let labels: Vec<String> = (0..HEIGHT)
+            .map(|row| match row {
+                0 => ticks.apply(y_axis.max),
+                r if r == HEIGHT - 1 => ticks.apply(y_axis.min),
+                r if r == HEIGHT / 2 => ticks.apply(y_axis.label(0.5)),
+                _ => String::new(),
+            })
+            .collect();
+        let margin = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let mut lines = vec![self.y_label(format)];
+        for (row, label) in cells.iter().zip(&labels) {
+            let axis = if label.is_empty() { '│' } else { '┤' };
+            let mut line = format!("{:>margin$} {}", label, axis);
+            for (dots, index) in row {
+                let c = char::from_u32(0x2800 + *dots as u32).unwrap_or(' ');
+                if colour && *dots != 0 {
+                    line.push_str(&format!("\x1b[{}m{}\x1b[0m", COLOURS[index % COLOURS.len()].0, c));
+                } else {
+                    line.push(c);
+                }
+            }
+            lines.push(line.trim_end_matches('\u{2800}').to_owned());
+        }
+        lines.push(format!("{:>margin$} └{}", "", "─".repeat(WIDTH)));
+
+        // The ends and middle of the x axis, spread so they do not overlap
+        let (left, middle, right) = (ticks.apply(x_axis.min), ticks.apply(x_axis.label(0.5)), ticks.apply(x_axis.max));
+        let mut axis = format!("{:>margin$}  {}", "", left);
+        let middle_at = margin + 2 + WIDTH / 2 - middle.chars().count() / 2;
+        if middle_at > axis.chars().count() {
+            axis.push_str(&" ".repeat(middle_at - axis.chars().count()));
+            axis.push_str(&middle);
+        }
+        let right_at = (margin + 2 + WIDTH).saturating_sub(right.chars().count());
+        axis.push_str(&" ".repeat(right_at.saturating_sub(axis.chars().count()).max(1)));
+        axis.push_str(&right);
+        lines.push(axis);
+        let x_label = self.x().header(format);
+        lines.push(format!("{}{}", " ".repeat(margin + 2 + (WIDTH / 2).saturating_sub(x_label.chars().count() / 2)), x_label));
+
+        if self.curves().len() > 1 {
+            let legend: Vec<String> = self.curves().iter().enumerate()
+                .map(|(index, curve)| {
+                    let (ansi, name) = COLOURS[index % COLOURS.len()];
+                    if colour { format!("\x1b[{}m──\x1b[0m {}", ansi, curve.name) } else { format!("{} ({})", curve.name, name) }
+                })
+                .collect();
+            lines.push(legend.join("   "));
+        }
+        lines.join("\n")
+    }
+
+    /// The plot as an SVG image, with the same axes and labels as the terminal plot
+    pub fn svg(&self, format: &Format) -> String {
+        let (x_axis, y_axis) = self.axes();
+        let ticks = tick_format(format);
+        let (left, top) = (SVG_MARGIN, SVG_MARGIN / 2.);
+        let (width, height) = (SVG_WIDTH - SVG_MARGIN * 1.5, SVG_HEIGHT - SVG_MARGIN * 1.5);
+        let px = |t: f64| left + t * width;
+        let py = |t: f64| top + (1. - t) * height;
+
+        let mut out = vec![
+            format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">", SVG_WIDTH, SVG_HEIGHT),
+            format!("<rect width=\"{}\" height=\"{}\" fill=\"white\"/>", SVG_WIDTH, SVG_HEIGHT),
+            format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>", left, top, width, height),
+        ];
+        for t in [0., 0.5, 1.] {
+            out.push(format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>", px(t), top + height + 16., escape(&ticks.apply(x_axis.label(t)))));
+            out.push(format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", left - 6., py(t) + 4., escape(&ticks.apply(y_axis.label(t)))));
+        }
+        out.push(format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>", px(0.5), top + height + 36., escape(&self.x().header(format))));
+        out.push(format!("<text x=\"{:.1}\" y=\"{:.1}\">{}</text>", left, top - 8., escape(&self.y_label(format))));
+
+        for (index, curve) in self.curves().iter().enumerate() {
+            let colour = COLOURS[index % COLOURS.len()].1;
+            // Each unbroken run of points is one polyline
+            for run in self.points(curve, &x_axis, &y_axis).split(Option::is_none) {
+                let points: Vec<String> = run.iter().flatten().map(|(x, y)| format!("{:.2},{:.2}", px(*x), py(*y))).collect();
+                if !points.is_empty() {
+                    out.push(format!("<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>", points.join(" "), colour));
+                }
+            }
+            if self.curves().len() > 1 {
+                out.push(format!(
+                    "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\" text-anchor=\"end\">{}</text>",
+                    left + width - 6., top + 16. * (index + 1) as f64, colour, escape(&curve.name),
+                ));
+            }
+        }
+        out.push("</svg>".to_owned());
+        out.join("\n") + "\n"
+    }
+}
+
+/// The bit of a braille character for the dot in a column (0 or 1) and row (0 to 3)
+fn braille_bit(column: usize, row: usize) -> u8 {
+    const BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+    BITS[column][row]
+}
+
+/// Set the dots on the line from a to b, by Bresenham's algorithm
+fn line(a: (i64, i64), b: (i64, i64), set: &mut impl FnMut(i64, i64)) {
+    let (mut x, mut y) = a;
+    let (dx, dy) = ((b.0 - x).abs(), -(b.1 - y).abs());
+    let (sx, sy) = ((b.0 - x).signum(), (b.1 - y).signum());
+    let mut error = dx + dy;
+    loop {
+        set(x, y);
+        if (x, y) == b {
+            return;
+        }
+        let twice = 2 * error;
+        if twice >= dy {
+            error += dy;
+            x += sx;
+        }
+        if twice <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Escape text for XML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// The number of points on a plotted curve if no count is given
+const PLOT_COUNT: f64 = 200.;
+
+/// `plot(f, g, x, a .. b, count, log)`: curves, labelled by their text, over a sweep. The y axis is logarithmic with `logy`, and both are with `loglog`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plot {
+    pub curves: Vec<(Expr, String)>,
+    pub sweep: Sweep,
+    pub log_y: bool,
+}
+
+impl Plot {
+    /// The values of each curve over the sweep. The curves must share a unit so they can share an axis.
+    pub fn eval(&self, ctx: &Context) -> Result<Chart> {
+        let mut sweep = self.sweep.clone();
+        if sweep.count.is_none() {
+            sweep.count = Some(Expr::Number(Number::new(PLOT_COUNT, Unit::one())));
+        }
+        let mut columns = Vec::new();
+        for (expr, label) in &self.curves {
+            // The table has the variable and then the curve
+            let mut table = sweep.tabulate(ctx, expr, label)?;
+            let curve = table.columns.remove(1);
+            if columns.is_empty() {
+                columns.push(table.columns.remove(0));
+            } else if columns[1].unit != curve.unit {
+                let (first, other) = (columns[1].header(&Format::default()), curve.header(&Format::default()));
+                return Err(CalcError::Unit(format!("The curves must have the same units to share an axis, but {} and {} do not", first, other)));
+            }
+            columns.push(curve);
+        }
+        Ok(Chart { table: Table { columns }, log_x: sweep.spacing == Spacing::Log, log_y: self.log_y })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_near_zero_are_zero() {
+        let axis = Axis { min: -1., max: 1.000035, log: false };
+        assert_eq!(axis.label(0.5), 0.);
+        assert_eq!(axis.label(1.), 1.000035);
+        let axis = Axis { min: -1e-5, max: 3e-5, log: false };
+        assert_eq!(axis.label(0.5), 1e-5);
+    }
+
+    #[test]
+    fn the_middle_of_a_symmetric_plot_is_labelled_zero() {
+        let mut ctx = Context::new();
+        let Ok(crate::context::Outcome::Plot(chart)) = ctx.execute("plot(x^3 + 1e-5, x, -1.3 .. 1.3, 7)") else {
+            panic!("plot did not draw");
+        };
+        let plot = chart.render(&Format::default(), false);
+        let middle = plot.lines().nth(1 + HEIGHT / 2).unwrap();
+        assert!(middle.trim_start().starts_with("0.00 ┤"), "{}", plot);
+    }
+}
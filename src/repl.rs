@@ -0,0 +1,451 @@
+use std::borrow::Cow;
+
+use calculator::{continues, split_format, split_script, strip_comment, tokenize, Context, Format, Number, TokenKind, Unit, KEYWORDS};
+use rustyline::{
+    completion::{Completer, Pair},
+    highlight::{CmdKind, Highlighter},
+    hint::{Hint, Hinter},
+    validate::{ValidationContext, ValidationResult, Validator},
+    Helper,
+};
+
+/// Lines the REPL understands besides expressions
+pub const COMMANDS: &[&str] = &[":format", ":output", ":save", ":load", ":symbol", "help", "apropos", "exit"];
+
+/// Arguments accepted by the commands that take one
+const COMMAND_ARGS: &[(&str, &[&str])] = &[
+    (":format", &["auto", "fixed", "sci", "eng"]),
+    (":output", &["text", "json", "csv"]),
+];
+
+// ANSI colours used to highlight the line
+const NUMBER_COLOR: &str = "\x1b[36m";
+const UNIT_COLOR: &str = "\x1b[32m";
+const CONSTANT_COLOR: &str = "\x1b[35m";
+const FUNCTION_COLOR: &str = "\x1b[34m";
+const VARIABLE_COLOR: &str = "\x1b[33m";
+const OPERATOR_COLOR: &str = "\x1b[1m";
+const ERROR_COLOR: &str = "\x1b[4;31m";
+const HINT_COLOR: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// Completes, hints and highlights the REPL line. Holds a copy of the session's context and format, which must be refreshed after every line.
+pub struct CalcHelper {
+    ctx: Context,
+    format: Format,
+}
+
+/// A hint shown after the cursor: either the rest of a name and a description of it, or a preview of the result. Only the rest of a name is inserted when the hint is accepted.
+pub struct CalcHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for CalcHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+/// A description of a number for completion lists, such as `length, 100000 cm`
+fn describe(n: &Number) -> String {
+    let value = n.format(&Format::default());
+    match n.u.dimension_name() {
+        Some("dimensionless") => value,
+        Some(dimension) => format!("{}, {}", dimension, value),
+        None => value,
+    }
+}
+
+/// Length of the numeric literal at the start of a word, such as the `3e5` of `3e5km`
+fn numeric_prefix_len(word: &str) -> usize {
+    let bytes = word.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.' || bytes[i] == b'_') {
+        i += 1;
+    }
+    // An exponent only counts if digits follow it
+    if i > 0 && i + 1 < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if bytes[j] == b'-' {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            i = j;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+    i
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl CalcHelper {
+    pub fn new(ctx: &Context, format: Format) -> Self {
+        Self { ctx: ctx.clone(), format }
+    }
+
+    /// Replace the copies of the context and format, so that new variables are offered
+    pub fn update(&mut self, ctx: &Context, format: Format) {
+        self.ctx = ctx.clone();
+        self.format = format;
+    }
+
+    /// The colour of a name, or None if it is unknown
+    fn name_color(&self, name: &str) -> Option<&'static str> {
+        if self.ctx.variable(name).is_some() || self.ctx.symbol(name).is_some() {
+            Some(VARIABLE_COLOR)
+        } else if self.ctx.function(name).is_some() {
+            Some(FUNCTION_COLOR)
+        } else if self.ctx.unit(name).is_some() || self.ctx.scale(name).is_some() {
+            Some(UNIT_COLOR)
+        } else if self.ctx.constant(name).is_some() {
+            Some(CONSTANT_COLOR)
+        } else {
+            None
+        }
+    }
+
+    /// Colour an expression, marking unknown names and unbalanced parentheses as errors
+    fn highlight_expr(&self, text: &str) -> String {
+        let tokens = tokenize(text);
+
+        // Find the parentheses and brackets without a partner
+        let mut unbalanced = vec![false; tokens.len()];
+        let mut open: Vec<(usize, TokenKind)> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            let partner = match token.kind {
+                TokenKind::LParen | TokenKind::LBracket => {
+                    open.push((i, token.kind.clone()));
+                    continue;
+                },
+                TokenKind::RParen => TokenKind::LParen,
+                TokenKind::RBracket => TokenKind::LBracket,
+                _ => continue,
+            };
+            match open.last() {
+                Some((_, kind)) if *kind == partner => {
+                    open.pop();
+                },
+                _ => unbalanced[i] = true,
+            }
+        }
+        for (i, _) in open {
+            unbalanced[i] = true;
+        }
+
+        let assertion = tokens.first().is_some_and(|t| t.kind == TokenKind::Name("assert".to_owned()));
+        let mut out = String::new();
+        let mut last = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            out.push_str(&text[last..token.start]);
+            let color = match &token.kind {
+                TokenKind::Number(_) => NUMBER_COLOR,
+                // The target of an assignment does not need to exist yet
+                TokenKind::Name(_) if i == 0 && tokens.get(1).is_some_and(|t| t.kind == TokenKind::Equals) => VARIABLE_COLOR,
+                TokenKind::Name(name) if KEYWORDS.contains(&name.as_str()) => OPERATOR_COLOR,
+                // The dimension an assertion compares with
+                TokenKind::Name(name) if assertion && Unit::dimension_names().any(|d| d.split(' ').any(|w| w == name)) => OPERATOR_COLOR,
+                TokenKind::Name(name) => self.name_color(name).unwrap_or(ERROR_COLOR),
+                TokenKind::Unknown(_) => ERROR_COLOR,
+                TokenKind::LParen | TokenKind::RParen | TokenKind::LBracket | TokenKind::RBracket if unbalanced[i] => ERROR_COLOR,
+                _ => OPERATOR_COLOR,
+            };
+            out.push_str(&format!("{}{}{}", color, &text[token.start..token.end], RESET));
+            last = token.end;
+        }
+        out.push_str(&text[last..]);
+        out
+    }
+
+    /// The result of the line, if it is a single statement which can be evaluated
+    fn preview(&self, line: &str) -> Option<String> {
+        let statements = split_script(line);
+        let [statement] = statements.as_slice() else {
+            return None;
+        };
+        let line = statement.text.as_str();
+        if line.starts_with(':') {
+            return None;
+        }
+        let (expr, format) = match split_format(line) {
+            Some((expr, spec)) => (expr, Format::parse(spec).ok()?),
+            None => (line, self.format),
+        };
+        // Slow statements are never evaluated, and tables, plots and dimensional analyses would not fit in a hint anyway
+        let result = self.ctx.preview(expr)?.ok()?.format(&format);
+        if result == expr.trim() {
+            return None;
+        }
+        Some(result)
+    }
+
+    fn unit_candidates(&self, prefix: &str) -> Vec<(String, String)> {
+        let scales = self.ctx.scales()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, scale)| (name.to_owned(), format!("scale, n {} = {}", name, scale.rule())));
+        self.ctx.units()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, n)| (name.to_owned(), describe(n)))
+            .chain(scales)
+            .collect()
+    }
+
+    /// Everything which can start an operand: functions, variables, constants and units
+    fn operand_candidates(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        for (name, function) in self.ctx.functions() {
+            if name.starts_with(prefix) {
+                out.push((format!("{}(", name), format!("function, {}", function.unit_rule())));
+            }
+        }
+        for (name, n) in self.ctx.variables() {
+            if name.starts_with(prefix) {
+                out.push((name.to_owned(), format!("variable, {}", describe(n))));
+            }
+        }
+        for (name, expr) in self.ctx.symbols() {
+            if name.starts_with(prefix) {
+                out.push((name.to_owned(), format!("variable, {}", expr.format(&Format::default()))));
+            }
+        }
+        for (name, q) in self.ctx.numbers() {
+            if name.starts_with(prefix) {
+                out.push((name.to_owned(), format!("{}", q)));
+            }
+        }
+        for (name, n) in self.ctx.constants() {
+            if name.starts_with(prefix) {
+                out.push((name.to_owned(), describe(n)));
+            }
+        }
+        out.extend(self.unit_candidates(prefix));
+        out
+    }
+
+    /// Find the start of the word being completed and the names (with descriptions) which could complete it
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<(String, String)>) {
+        let text = &line[..pos];
+
+        // Commands and their arguments
+        if text.starts_with(':') {
+            if !text.contains(' ') {
+                let out = COMMANDS.iter()
+                    .filter(|c| c.starts_with(text))
+                    .map(|c| (c.to_string(), "command".to_owned()))
+                    .collect();
+                return (0, out);
+            }
+            let (command, arg) = text.split_once(' ').unwrap();
+            let arg = arg.trim_start();
+            let out = COMMAND_ARGS.iter()
+                .filter(|(c, _)| *c == command)
+                .flat_map(|(_, args)| args.iter())
+                .filter(|a| a.starts_with(arg))
+                .map(|a| (a.to_string(), String::new()))
+                .collect();
+            return (pos - arg.len(), out);
+        }
+
+        let start = text.rfind(|c: char| !is_name_char(c)).map(|i| i + 1).unwrap_or(0);
+        let word = &text[start..];
+
+        // A word like `3k` is a number followed by a unit
+        let numeric = numeric_prefix_len(word);
+        if numeric > 0 {
+            return (start + numeric, self.unit_candidates(&word[numeric..]));
+        }
+
+        // A unit is expected after a number literal
+        let before = text[..start].trim_end();
+        let previous_word = &before[before.rfind(|c: char| !is_name_char(c)).map(|i| i + 1).unwrap_or(0)..];
+        if !previous_word.is_empty() && numeric_prefix_len(previous_word) == previous_word.len() {
+            return (start, self.unit_candidates(word));
+        }
+
+        let mut out = self.operand_candidates(word);
+        if before.is_empty() {
+            out.extend(COMMANDS.iter()
+                .filter(|c| !c.starts_with(':') && c.starts_with(word))
+                .map(|c| (c.to_string(), "command".to_owned())));
+        }
+        (start, out)
+    }
+}
+
+impl Completer for CalcHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, mut candidates) = self.candidates(line, pos);
+        candidates.sort();
+        candidates.dedup_by(|a, b| a.0 == b.0);
+        let pairs = candidates.into_iter()
+            .map(|(name, description)| Pair {
+                display: if description.is_empty() { name.clone() } else { format!("{} ({})", name, description) },
+                replacement: name,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for CalcHelper {
+    type Hint = CalcHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<CalcHint> {
+        if pos < line.len() {
+            return None;
+        }
+
+        // Complete a name once it is the only candidate
+        let (start, candidates) = self.candidates(line, pos);
+        let typed = &line[start..pos];
+        if !typed.is_empty() && candidates.len() == 1 && candidates[0].0 != typed {
+            let (name, description) = &candidates[0];
+            let completion = name[typed.len()..].to_owned();
+            let display = if description.is_empty() {
+                completion.clone()
+            } else {
+                format!("{}  ({})", completion, description)
+            };
+            return Some(CalcHint { display, completion: Some(completion) });
+        }
+
+        // Otherwise preview the result
+        let result = self.preview(line)?;
+        Some(CalcHint { display: format!("  = {}", result), completion: None })
+    }
+}
+
+impl Highlighter for CalcHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.trim_start().starts_with(':') || COMMANDS.iter().any(|c| c.starts_with(line.trim()) && !line.trim().is_empty()) {
+            return Cow::Borrowed(line);
+        }
+        let code = strip_comment(line);
+        let comment = &line[code.len()..];
+        let code = match split_format(code) {
+            Some((expr, spec)) => format!("{}{}::{}{}", self.highlight_expr(expr), HINT_COLOR, spec, RESET),
+            None => self.highlight_expr(code),
+        };
+        if comment.is_empty() {
+            Cow::Owned(code)
+        } else {
+            Cow::Owned(format!("{}{}{}{}", code, HINT_COLOR, comment, RESET))
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_COLOR, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+impl Validator for CalcHelper {
+    /// Keep reading lines while the input ends with a backslash or leaves a parenthesis open
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if continues(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Helper for CalcHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unknown_names_are_errors() {
+        let mut ctx = Context::new();
+        ctx.execute("x = 2 km").unwrap();
+        ctx.execute("y = 3 z").unwrap();
+        let helper = CalcHelper::new(&ctx, Format::default());
+        assert_eq!(helper.name_color("x"), Some(VARIABLE_COLOR));
+        // y is defined in terms of z, which has no value yet
+        assert_eq!(helper.name_color("y"), Some(VARIABLE_COLOR));
+        assert_eq!(helper.name_color("sin"), Some(FUNCTION_COLOR));
+        assert_eq!(helper.name_color("km"), Some(UNIT_COLOR));
+        assert_eq!(helper.name_color("nonsense"), None);
+    }
+
+    #[test]
+    fn highlighting_marks_errors_as_you_type() {
+        let helper = CalcHelper::new(&Context::new(), Format::default());
+        let error = |text: &str| format!("{}{}{}", ERROR_COLOR, text, RESET);
+        assert_eq!(helper.highlight_expr("2 km"), format!("{}2{} {}km{}", NUMBER_COLOR, RESET, UNIT_COLOR, RESET));
+        assert!(helper.highlight_expr("sqrt(2").contains(&error("(")));
+        assert!(helper.highlight_expr("2)").contains(&error(")")));
+        assert!(helper.highlight_expr("[1, 2)").contains(&error("[")));
+        assert!(!helper.highlight_expr("sqrt((2))").contains(ERROR_COLOR));
+        assert!(helper.highlight_expr("3 kmm").contains(&error("kmm")));
+    }
+
+    #[test]
+    fn previews_have_no_side_effects() {
+        let helper = CalcHelper::new(&Context::new(), Format::default());
+        assert_eq!(helper.preview("x = 3 km").as_deref(), Some("300000 cm"));
+        assert_eq!(helper.preview("x"), None);
+        assert_eq!(helper.preview("1 + 2 :: sci 2").as_deref(), Some("3.0e0"));
+        assert_eq!(helper.preview("3 km /"), None);
+    }
+
+    fn names(helper: &CalcHelper, line: &str) -> (usize, Vec<String>) {
+        let (start, candidates) = helper.candidates(line, line.len());
+        (start, candidates.into_iter().map(|(name, _)| name).collect())
+    }
+
+    #[test]
+    fn completion_depends_on_position() {
+        let mut ctx = Context::new();
+        ctx.execute("mass = 3 msun").unwrap();
+        let helper = CalcHelper::new(&ctx, Format::default());
+        // Units after a number, whether or not there is a space
+        let (start, units) = names(&helper, "3 k");
+        assert_eq!(start, 2);
+        assert!(units.contains(&"km".to_owned()) && units.contains(&"kg".to_owned()));
+        assert!(!units.contains(&"kb".to_owned()), "constants are not units");
+        let (start, units) = names(&helper, "3km");
+        assert_eq!((start, units.contains(&"km".to_owned())), (1, true));
+        // Functions with a parenthesis, variables and constants as operands
+        let (start, operands) = names(&helper, "2 * sq");
+        assert_eq!(start, 4);
+        assert!(operands.contains(&"sqrt(".to_owned()));
+        assert!(names(&helper, "ma").1.contains(&"mass".to_owned()));
+        // Commands at the start of a line, and their arguments
+        assert!(names(&helper, ":fo").1.contains(&":format".to_owned()));
+        assert!(names(&helper, ":output j").1.contains(&"json".to_owned()));
+    }
+
+    #[test]
+    fn hints_describe_the_only_candidate() {
+        let mut ctx = Context::new();
+        ctx.execute("distance = 2 km").unwrap();
+        let helper = CalcHelper::new(&ctx, Format::default());
+        let (_, candidates) = helper.candidates("dista", 5);
+        assert_eq!(candidates, vec![("distance".to_owned(), "variable, length, 200000 cm".to_owned())]);
+    }
+
+    #[test]
+    fn symbols_are_offered_for_completion() {
+        let mut ctx = Context::new();
+        ctx.execute("yield = 3 z").unwrap();
+        let helper = CalcHelper::new(&ctx, Format::default());
+        assert!(helper.operand_candidates("yie").contains(&("yield".to_owned(), "variable, 3 z".to_owned())));
+    }
+}
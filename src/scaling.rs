@@ -0,0 +1,68 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::{BinaryOp, Expr}, number::Number, numeric, symbolic, unit::Unit, util::EPSILON};
+
+/// `scaling(expr, M = 1 msun, R = 10 km)`: the expression as its value at the reference values times a power of each variable over its reference
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scaling {
+    pub expr: Expr,
+    pub references: Vec<(String, Expr)>,
+}
+
+impl Scaling {
+    /// The expression as fiducial value × (M / 1 msun)^a × ..., where each exponent is the logarithmic derivative at the reference values. The exponents come from the symbolic derivative where there is one, and numerically otherwise.
+    pub fn eval(&self, ctx: &Context) -> Result<Expr> {
+        let mut scope = ctx.clone();
+        let mut points = Vec::new();
+        for (name, reference) in &self.references {
+            let value = reference.eval(ctx)?.quantity("A reference value")?;
+            let Some(x) = value.value().filter(|x| *x > 0.) else {
+                return Err(CalcError::Domain(format!("The reference value of {} must be a single positive number, not {}", name, value)));
+            };
+            scope.set_variable(name, value.clone());
+            points.push((x, value.u));
+        }
+        let fiducial = self.expr.eval(&scope)?;
+        let Some(f) = fiducial.value().filter(|f| *f != 0.) else {
+            return Err(CalcError::Domain(format!("The expression must be a single nonzero number at the reference values, not {}", fiducial)));
+        };
+
+        // The symbolic derivative needs the variables free, so it is only taken if none of them already has a value
+        let free = self.references.iter().all(|(name, _)| ctx.variable(name).is_none());
+        let symbolic = if free { symbolic::partial(&self.expr, ctx).ok() } else { None };
+
+        let mut result = Expr::Number(fiducial.clone());
+        for ((name, reference), (x, unit)) in self.references.iter().zip(points) {
+            let exact = symbolic.as_ref()
+                .and_then(|expr| symbolic::derivative(expr, name).ok())
+                .and_then(|derivative| derivative.eval(&scope).ok())
+                .and_then(|slope| slope.value().filter(|_| slope.u + unit == fiducial.u))
+                .map(|slope| slope * x / f);
+            let exponent = match exact {
+                Some(exponent) => exponent,
+                None => {
+                    let mut shifted = scope.clone();
+                    numeric::derivative(|t| {
+                        shifted.set_variable(name, Number::new(x * t.exp(), unit));
+                        let n = self.expr.eval(&shifted)?;
+                        n.value().map(|q| q.abs().ln()).ok_or_else(|| CalcError::Domain("The expression must have a single value, not an array".to_owned()))
+                    }, 0.)?
+                },
+            };
+            if exponent.abs() < EPSILON {
+                continue;
+            }
+            // A reference of 1 msun is written as msun
+            let reference = match reference {
+                Expr::Binary(BinaryOp::Mul, one, unit) if **one == Expr::Number(Number::new(1., Unit::one())) => (**unit).clone(),
+                reference => reference.clone(),
+            };
+            let ratio = Expr::Binary(BinaryOp::Div, Box::new(Expr::Name(name.clone())), Box::new(reference));
+            let factor = if (exponent - 1.).abs() < EPSILON {
+                ratio
+            } else {
+                Expr::Binary(BinaryOp::Expon, Box::new(ratio), Box::new(Expr::Number(Number::new(exponent, Unit::one()))))
+            };
+            result = Expr::Binary(BinaryOp::Mul, Box::new(result), Box::new(factor));
+        }
+        Ok(result)
+    }
+}
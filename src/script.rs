@@ -0,0 +1,266 @@
+use crate::lexer::{tokenize, TokenKind};
+
+/// A statement of a script and the lines it came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptStatement {
+    pub text: String,
+    /// The line the statement starts on, counting from 1
+    pub line: usize,
+    /// The line the statement ends on
+    pub last_line: usize,
+}
+
+/// The part of a line before any `#` or `//` comment
+pub fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' if !in_quotes => return &line[..i],
+            '/' if !in_quotes && previous == '/' => return &line[..i - 1],
+            _ => (),
+        }
+        previous = c;
+    }
+    line
+}
+
+/// Split a line at the `::` which starts a format for that line only, as in `x :: sci 3`. A `::` inside a string is part of the string.
+pub fn split_format(line: &str) -> Option<(&str, &str)> {
+    let tokens = tokenize(line);
+    let colon = TokenKind::Unknown(':');
+    let (first, second) = tokens.windows(2)
+        .map(|pair| (&pair[0], &pair[1]))
+        .find(|(a, b)| a.kind == colon && b.kind == colon && a.end == b.start)?;
+    Some((&line[..first.start], &line[second.end..]))
+}
+
+/// The number of parentheses and brackets a line leaves open
+fn open_brackets(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_quotes = false;
+    for c in line.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' | '[' if !in_quotes => depth += 1,
+            ')' | ']' if !in_quotes => depth -= 1,
+            _ => (),
+        }
+    }
+    depth
+}
+
+/// Whether text continues on the next line, because it ends with a backslash or leaves a parenthesis open
+pub fn continues(text: &str) -> bool {
+    let last = text.lines().last().unwrap_or("");
+    let depth: i32 = text.lines().map(|line| open_brackets(strip_comment(line))).sum();
+    strip_comment(last).trim_end().ends_with('\\') || depth > 0
+}
+
+/// Split a script into statements. Comments and blank lines are dropped, lines ending in a backslash or inside open parentheses are joined to the next line, and `;` separates statements on one line.
+pub fn split_script(source: &str) -> Vec<ScriptStatement> {
+    let mut statements = Vec::new();
+    let mut pending = String::new();
+    let mut first_line = 0;
+    for (i, line) in source.lines().enumerate() {
+        let code = strip_comment(line).trim_end();
+        if pending.is_empty() {
+            first_line = i + 1;
+        }
+        let code = match code.strip_suffix('\\') {
+            Some(code) => {
+                pending.push_str(code);
+                pending.push(' ');
+                continue;
+            },
+            None => code,
+        };
+        pending.push_str(code);
+        if open_brackets(&pending) > 0 {
+            pending.push(' ');
+            continue;
+        }
+        for text in split_statements(&pending) {
+            statements.push(ScriptStatement { text: text.trim().to_owned(), line: first_line, last_line: i + 1 });
+        }
+        pending.clear();
+    }
+    // A script which ends inside parentheses still produces a (broken) statement, so that its error is reported
+    for text in split_statements(&pending) {
+        statements.push(ScriptStatement { text: text.trim().to_owned(), line: first_line, last_line: source.lines().count() });
+    }
+    statements
+}
+
+/// Split a line into the statements separated by `;`, ignoring separators inside parentheses or quotes
+fn split_statements(text: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut depth = 0i32;
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' | '[' if !in_quotes => depth += 1,
+            ')' | ']' if !in_quotes => depth -= 1,
+            ';' if !in_quotes && depth <= 0 => {
+                statements.push(&text[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    statements.push(&text[start..]);
+    statements.retain(|s| !s.trim().is_empty());
+    statements
+}
+
+/// The marker which starts a result written into a script by [`annotate`]
+pub const ANNOTATION: &str = "# =>";
+/// Annotations are aligned to the end of the longest annotated line, but no further right than this column
+const MAX_ANNOTATION_COLUMN: usize = 48;
+
+/// Whether a line holds only a further row of a multi-line annotation, as annotate writes beneath the first
+fn is_continuation(line: &str) -> bool {
+    line.trim_start().starts_with(ANNOTATION)
+}
+
+/// A line without the annotation left by an earlier run of annotate
+fn strip_annotation(line: &str) -> &str {
+    let code = strip_comment(line);
+    match line[code.len()..].find(ANNOTATION) {
+        Some(i) => line[..code.len() + i].trim_end(),
+        None => line,
+    }
+}
+
+/// Rewrite a script with results appended to lines as `# => result` comments. Each result is paired with the number of the line (counting from 1) it belongs after, as in [`ScriptStatement::last_line`]; results on the same line are separated by `;`.
+/// The further rows of a multi-line result, such as a table, go on lines of their own beneath, each starting with `# =>` so that running the script skips them. Earlier annotations are replaced and everything else is kept as it was.
+pub fn annotate(source: &str, results: &[(usize, String)]) -> String {
+    let lines: Vec<(usize, &str)> = source.lines().enumerate()
+        .filter(|(_, line)| !is_continuation(line))
+        .map(|(i, line)| (i, strip_annotation(line)))
+        .collect();
+    let mut annotations: Vec<Vec<&str>> = vec![Vec::new(); source.lines().count()];
+    for (line, result) in results {
+        if let Some(annotation) = annotations.get_mut(line.wrapping_sub(1)) {
+            annotation.push(result);
+        }
+    }
+    let column = lines.iter()
+        .filter(|(i, _)| !annotations[*i].is_empty())
+        .map(|(_, line)| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(MAX_ANNOTATION_COLUMN);
+
+    let mut out = String::new();
+    for (i, line) in lines {
+        if annotations[i].is_empty() {
+            out.push_str(line);
+        } else {
+            let text = annotations[i].join("; ");
+            let width = column.max(line.chars().count());
+            for (row, result) in text.lines().enumerate() {
+                let code = if row == 0 { line } else { "" };
+                if row > 0 {
+                    out.push('\n');
+                }
+                out.push_str(format!("{:<width$}  {} {}", code, ANNOTATION, result).trim_end());
+            }
+        }
+        out.push('\n');
+    }
+    if !source.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+/// The annotations of a script as annotate writes them: one entry for each line of code, with its number (counting from 1) and its result, whose rows are joined by newlines
+pub fn annotations(source: &str) -> Vec<(usize, Option<String>)> {
+    let mut out: Vec<(usize, Option<String>)> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        if is_continuation(line) {
+            let row = line.trim_start()[ANNOTATION.len()..].trim();
+            if let Some((_, Some(result))) = out.last_mut() {
+                result.push('\n');
+                result.push_str(row);
+            }
+            continue;
+        }
+        let code = strip_annotation(line);
+        let result = line[code.len()..].trim_start().strip_prefix(ANNOTATION).map(|result| result.trim().to_owned());
+        out.push((i + 1, result));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statements(source: &str) -> Vec<(usize, String)> {
+        split_script(source).into_iter().map(|s| (s.line, s.text)).collect()
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_dropped() {
+        let source = "# a comment\nx = 2 km  # the distance\n\n// another\ny = x / 2 // half\nprint \"# not a comment\" x\n";
+        assert_eq!(statements(source), vec![
+            (2, "x = 2 km".to_owned()),
+            (5, "y = x / 2".to_owned()),
+            (6, "print \"# not a comment\" x".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn semicolons_separate_statements_outside_brackets_and_quotes() {
+        assert_eq!(statements("a = 1; b = 2;c = 3"), vec![(1, "a = 1".to_owned()), (1, "b = 2".to_owned()), (1, "c = 3".to_owned())]);
+        assert_eq!(statements("print \"a; b\" 1; 2"), vec![(1, "print \"a; b\" 1".to_owned()), (1, "2".to_owned())]);
+    }
+
+    #[test]
+    fn statements_continue_after_backslashes_and_open_brackets() {
+        assert_eq!(statements("x = 1 + \\\n  2\ny = 3"), vec![(1, "x = 1 +    2".to_owned()), (3, "y = 3".to_owned())]);
+        assert_eq!(statements("sqrt(1 +\n 3)\n[1,\n 2]"), vec![(1, "sqrt(1 +  3)".to_owned()), (3, "[1,  2]".to_owned())]);
+        // A statement left open is still returned, so its error is reported
+        assert_eq!(statements("sqrt(2"), vec![(1, "sqrt(2".to_owned())]);
+        assert!(continues("sqrt(2") && continues("1 + \\") && !continues("1 + 2"));
+    }
+
+    #[test]
+    fn formats_split_outside_strings() {
+        assert_eq!(split_format("x :: sci 3"), Some(("x ", " sci 3")));
+        assert_eq!(split_format("print \"a :: b\" x"), None);
+        assert_eq!(split_format("x"), None);
+    }
+
+    #[test]
+    fn print_labels_results() {
+        let mut ctx = crate::context::Context::new();
+        ctx.execute("x = 2 km").unwrap();
+        let printed = ctx.execute("print \"distance:\" x \"and\" 2 x").unwrap().format(&crate::format::Format::default());
+        assert_eq!(printed, "distance: 200000 cm and 400000 cm");
+    }
+
+    #[test]
+    fn multi_line_annotations_round_trip() {
+        let source = "x = 2\ntable(x^2, x, 1 .. 2, 2)\nx + 1\n";
+        let results = [(1, "2".to_owned()), (2, "x  x^2\n1    1\n2    4".to_owned()), (3, "3".to_owned())];
+        let annotated = annotate(source, &results);
+
+        // The rows of the table are comments, so the annotated script runs the same statements
+        let statements = |text: &str| split_script(text).into_iter().map(|s| s.text).collect::<Vec<_>>();
+        assert_eq!(statements(&annotated), statements(source));
+
+        // Reading the annotations back gives the results, which is what --check compares
+        let read: Vec<_> = annotations(&annotated).into_iter().filter_map(|(line, result)| Some((line, result?))).collect();
+        assert_eq!(read, vec![(1, "2".to_owned()), (2, "x  x^2\n1    1\n2    4".to_owned()), (5, "3".to_owned())]);
+
+        // Annotating again, by the lines of the annotated script, replaces the old rows rather than adding to them
+        let again = [(1, "2".to_owned()), (2, "x  x^2\n1    1\n2    4".to_owned()), (5, "3".to_owned())];
+        assert_eq!(annotate(&annotated, &again), annotated);
+    }
+}
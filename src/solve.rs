@@ -0,0 +1,52 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::Expr, number::Number, numeric};
+
+/// `solve(lhs == rhs, x, guess)` or `solve(lhs == rhs, x, a .. b)`: the value of x which makes the sides equal
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solve {
+    pub lhs: Expr,
+    /// The other side of the equation, or zero if there is none
+    pub rhs: Option<Expr>,
+    pub variable: String,
+    /// A guess at the root, or the start of a range which contains it
+    pub start: Expr,
+    /// The end of the range which contains the root, if one is given
+    pub end: Option<Expr>,
+}
+
+impl Solve {
+    /// Find the root, which has the unit of the guess or range
+    pub fn eval(&self, ctx: &Context) -> Result<Number> {
+        let start = self.start.eval(ctx)?.quantity("The guess for solve")?;
+        let end = self.end.as_ref().map(|end| end.eval(ctx)?.quantity("The end of a range")).transpose()?;
+        let Some(a) = start.value() else {
+            return Err(CalcError::Domain("The guess for solve must be a single number".to_owned()));
+        };
+        let unit = start.u;
+        if let Some(end) = &end && end.u != unit {
+            return Err(CalcError::Unit(format!("The ends of the range {} .. {} have different units", start, end)));
+        }
+
+        // The difference between the sides, in the unit of both
+        let mut scope = ctx.clone();
+        let mut residual = |x: f64| -> Result<f64> {
+            scope.set_variable(&self.variable, Number::new(x, unit));
+            let lhs = self.lhs.eval(&scope)?;
+            let rhs = match &self.rhs {
+                Some(rhs) => rhs.eval(&scope)?,
+                None => Number::new(0., lhs.u),
+            };
+            if lhs.u != rhs.u {
+                return Err(CalcError::Unit(format!("The two sides of the equation have different units: {} and {}", lhs, rhs)));
+            }
+            lhs.sub(rhs)?.value().ok_or_else(|| CalcError::Domain("The equation must have a single value, not an array".to_owned()))
+        };
+
+        let (a, b) = match end.as_ref().and_then(Number::value) {
+            Some(b) => (a, b),
+            None if end.is_some() => return Err(CalcError::Domain("The end of the range for solve must be a single number".to_owned())),
+            None => numeric::bracket(&mut residual, a)?,
+        };
+        let root = if a == b { a } else { numeric::solve(&mut residual, a, b)? };
+        Ok(Number::new(root, unit))
+    }
+}
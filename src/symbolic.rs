@@ -0,0 +1,522 @@
+use crate::{
+    context::Context,
+    defaults::{Function, FUNCTIONS},
+    derivative::Derivative,
+    error::{CalcError, Result},
+    expr::{BinaryOp, Expr},
+    number::Number,
+    unit::Unit,
+    util::{fraction, EPSILON},
+};
+
+fn number(q: f64) -> Expr {
+    Expr::Number(Number::new(q, Unit::one()))
+}
+
+fn binary(op: BinaryOp, a: Expr, b: Expr) -> Expr {
+    Expr::Binary(op, Box::new(a), Box::new(b))
+}
+
+fn call(name: &str, arg: &Expr) -> Expr {
+    Expr::Call(name.to_owned(), vec![arg.clone()])
+}
+
+/// Whether the value of an expression can change with the variable x. A variable bound inside solve, integrate or diff is a different x.
+pub fn depends_on(expr: &Expr, x: &str) -> bool {
+    match expr {
+        Expr::Number(_) => false,
+        Expr::Name(name) => name == x,
+        Expr::Neg(e) | Expr::Level(e, _) => depends_on(e, x),
+        Expr::Binary(_, a, b) => depends_on(a, x) || depends_on(b, x),
+        Expr::Call(_, args) | Expr::List(args) => args.iter().any(|a| depends_on(a, x)),
+        Expr::Solve(solve) => {
+            let bound = solve.variable == x;
+            (!bound && (depends_on(&solve.lhs, x) || solve.rhs.as_ref().is_some_and(|r| depends_on(r, x))))
+                || depends_on(&solve.start, x)
+                || solve.end.as_ref().is_some_and(|e| depends_on(e, x))
+        },
+        Expr::Integral(integral) => {
+            (integral.variable != x && depends_on(&integral.integrand, x))
+                || depends_on(&integral.from, x)
+                || depends_on(&integral.to, x)
+        },
+        Expr::Derivative(derivative) => match &derivative.at {
+            Some(at) => (derivative.variable != x && depends_on(&derivative.expr, x)) || depends_on(at, x),
+            None => depends_on(&derivative.expr, x),
+        },
+    }
+}
+
+/// The derivative of f with respect to its argument i, for the functions which have one in closed form.
+/// Rounding functions are constant between their steps, so their derivative is 0 wherever it is defined.
+fn function_derivative(name: &str, args: &[Expr], i: usize) -> Option<Expr> {
+    use BinaryOp::*;
+    let one_over = |e: Expr| binary(Div, number(1.), e);
+    let neg = |e: Expr| Expr::Neg(Box::new(e));
+    let pow = |e: &Expr, p: f64| binary(Expon, e.clone(), number(p));
+    let root_pi = || call("sqrt", &Expr::Name("pi".to_owned()));
+    let same = || Expr::Call(name.to_owned(), args.to_vec());
+    let with = |name: &str, args: Vec<Expr>| Expr::Call(name.to_owned(), args);
+    Some(match (name, args, i) {
+        (_, [u], 0) => match name {
+            "sqrt" => one_over(binary(Mul, number(2.), call("sqrt", u))),
+            "cbrt" => one_over(binary(Mul, number(3.), pow(&call("cbrt", u), 2.))),
+            "exp" => call("exp", u),
+            "ln" => one_over(u.clone()),
+            "log10" => one_over(binary(Mul, u.clone(), call("ln", &number(10.)))),
+            "log2" => one_over(binary(Mul, u.clone(), call("ln", &number(2.)))),
+            "sin" => call("cos", u),
+            "cos" => neg(call("sin", u)),
+            "tan" => one_over(pow(&call("cos", u), 2.)),
+            "asin" => one_over(call("sqrt", &binary(Sub, number(1.), pow(u, 2.)))),
+            "acos" => neg(one_over(call("sqrt", &binary(Sub, number(1.), pow(u, 2.))))),
+            "atan" => one_over(binary(Add, number(1.), pow(u, 2.))),
+            "sinh" => call("cosh", u),
+            "cosh" => call("sinh", u),
+            "tanh" => one_over(pow(&call("cosh", u), 2.)),
+            "asinh" => one_over(call("sqrt", &binary(Add, pow(u, 2.), number(1.)))),
+            "acosh" => one_over(call("sqrt", &binary(Sub, pow(u, 2.), number(1.)))),
+            "atanh" => one_over(binary(Sub, number(1.), pow(u, 2.))),
+            "abs" => call("sign", u),
+            "floor" | "ceil" | "round" | "sign" => number(0.),
+            "erf" => binary(Mul, binary(Div, number(2.), root_pi()), call("exp", &neg(pow(u, 2.)))),
+            "erfc" => neg(function_derivative("erf", args, 0)?),
+            "erfcx" => binary(Sub, binary(Mul, binary(Mul, number(2.), u.clone()), call("erfcx", u)), binary(Div, number(2.), root_pi())),
+            // erf(inverf(u)) = u, so the derivative is one over erf' at inverf(u)
+            "inverf" => binary(Mul, binary(Div, root_pi(), number(2.)), call("exp", &pow(&call("inverf", u), 2.))),
+            "inverfc" => neg(binary(Mul, binary(Div, root_pi(), number(2.)), call("exp", &pow(&call("inverfc", u), 2.)))),
+            "gamma" => binary(Mul, call("gamma", u), call("digamma", u)),
+            "fact" => binary(Mul, call("fact", u), call("digamma", &binary(Add, u.clone(), number(1.)))),
+            "lgamma" | "ln_gamma" => call("digamma", u),
+            "digamma" => call("trigamma", u),
+            // W exp(W) = u, so dW/du = exp(-W) / (1 + W)
+            "lambertw" | "lambertwm1" => binary(Div, call("exp", &neg(call(name, u))), binary(Add, number(1.), call(name, u))),
+            _ => return None,
+        },
+        // The integrand of the incomplete gamma function, x^(a - 1) exp(-x) / gamma(a)
+        ("gammp", [a, x], 1) => binary(Div, binary(Mul, binary(Expon, x.clone(), binary(Sub, a.clone(), number(1.))), call("exp", &neg(x.clone()))), call("gamma", a)),
+        ("gammq", _, 1) => neg(function_derivative("gammp", args, 1)?),
+        // One over the derivative of gammp at w = invgammp(p, a)
+        ("invgammp", [_, a], 0) => binary(Mul, binary(Mul, call("gamma", a), call("exp", &same())), binary(Expon, same(), binary(Sub, number(1.), a.clone()))),
+        ("beta", [a, b], 0 | 1) => {
+            let own = if i == 0 { a } else { b };
+            binary(Mul, same(), binary(Sub, call("digamma", own), call("digamma", &binary(Add, a.clone(), b.clone()))))
+        },
+        ("betai", [a, b, x], 2) => binary(
+            Div,
+            binary(Mul, binary(Expon, x.clone(), binary(Sub, a.clone(), number(1.))), binary(Expon, binary(Sub, number(1.), x.clone()), binary(Sub, b.clone(), number(1.)))),
+            with("beta", vec![a.clone(), b.clone()]),
+        ),
+        ("invbetai", [_, a, b], 0) => binary(
+            Mul,
+            with("beta", vec![a.clone(), b.clone()]),
+            binary(Mul, binary(Expon, same(), binary(Sub, number(1.), a.clone())), binary(Expon, binary(Sub, number(1.), same()), binary(Sub, number(1.), b.clone()))),
+        ),
+        // The recurrences which give the derivative from orders nu and nu + 1, so that order 0 needs no negative order
+        ("besselj" | "bessely" | "besselk" | "besseli", [nu, x], 1) => {
+            let next = with(name, vec![binary(Add, nu.clone(), number(1.)), x.clone()]);
+            let op = if name == "besseli" { Add } else { Sub };
+            binary(op, binary(Mul, binary(Div, nu.clone(), x.clone()), same()), next)
+        },
+        _ => return None,
+    })
+}
+
+/// The derivative of an expression with respect to x, simplified
+pub fn derivative(expr: &Expr, x: &str) -> Result<Expr> {
+    simplify(&differentiate(expr, x)?)
+}
+
+fn differentiate(expr: &Expr, x: &str) -> Result<Expr> {
+    use BinaryOp::*;
+    if !depends_on(expr, x) {
+        return Ok(number(0.));
+    }
+    let d = |e: &Expr| differentiate(e, x);
+    Ok(match expr {
+        Expr::Number(_) => number(0.),
+        Expr::Name(_) => number(1.),
+        Expr::Neg(e) => Expr::Neg(Box::new(d(e)?)),
+        Expr::Binary(Add, a, b) => binary(Add, d(a)?, d(b)?),
+        Expr::Binary(Sub, a, b) => binary(Sub, d(a)?, d(b)?),
+        Expr::Binary(Mul, a, b) => binary(Add, binary(Mul, d(a)?, (**b).clone()), binary(Mul, (**a).clone(), d(b)?)),
+        // floor(a / b) only changes in steps, where the derivative is not defined
+        Expr::Binary(Mod, a, b) => binary(Sub, d(a)?, binary(Mul, d(b)?, call("floor", &binary(Div, (**a).clone(), (**b).clone())))),
+        Expr::Binary(Div, a, b) if !depends_on(b, x) => binary(Div, d(a)?, (**b).clone()),
+        Expr::Binary(Div, a, b) => binary(
+            Div,
+            binary(Sub, binary(Mul, d(a)?, (**b).clone()), binary(Mul, (**a).clone(), d(b)?)),
+            binary(Expon, (**b).clone(), number(2.)),
+        ),
+        Expr::Binary(Expon, a, b) if !depends_on(b, x) => binary(
+            Mul,
+            binary(Mul, (**b).clone(), binary(Expon, (**a).clone(), binary(Sub, (**b).clone(), number(1.)))),
+            d(a)?,
+        ),
+        // a^b = exp(b ln(a))
+        Expr::Binary(Expon, a, b) => binary(
+            Mul,
+            expr.clone(),
+            binary(Add, binary(Mul, d(b)?, call("ln", a)), binary(Div, binary(Mul, (**b).clone(), d(a)?), (**a).clone())),
+        ),
+        Expr::Call(name, args) if matches!(name.as_str(), "sum" | "mean" | "len" | "min" | "max") => match (name.as_str(), args.as_slice()) {
+            ("sum" | "mean", [u]) => call(name, &d(u)?),
+            ("len", _) => number(0.),
+            _ => return Err(CalcError::Domain(format!("{} has no derivative. Use diff(expr, {}, at) at a point", name, x))),
+        },
+        // The chain rule, through each argument which depends on x
+        Expr::Call(name, args) => {
+            let mut total = None;
+            for (i, u) in args.iter().enumerate().filter(|(_, u)| depends_on(u, x)) {
+                let Some(outer) = function_derivative(name, args, i) else {
+                    let argument = match FUNCTIONS.get(name.as_str()) {
+                        Some(Function::Special { args: names, .. }) if names.len() > 1 => format!(" with respect to its argument {}", names.get(i).unwrap_or(&"x")),
+                        _ => String::new(),
+                    };
+                    return Err(CalcError::Domain(format!("{} has no derivative in closed form{}. Use diff(expr, {}, at) at a point", name, argument, x)));
+                };
+                let term = binary(Mul, outer, d(u)?);
+                total = Some(match total {
+                    Some(sum) => binary(Add, sum, term),
+                    None => term,
+                });
+            }
+            total.unwrap_or_else(|| number(0.))
+        },
+        Expr::List(items) => Expr::List(items.iter().map(d).collect::<Result<_>>()?),
+        // A level changes by as much as the number on its scale, so its slope is a plain number
+        Expr::Level(level, _) => d(level)?,
+        Expr::Derivative(derivative) if derivative.at.is_none() => d(&differentiate(&derivative.expr, &derivative.variable)?)?,
+        Expr::Solve(_) | Expr::Integral(_) | Expr::Derivative(_) => {
+            return Err(CalcError::Domain(format!("Cannot differentiate {} symbolically. Use diff(expr, {}, at) at a point", expr, x)));
+        },
+    })
+}
+
+/// A product: a coefficient times factors raised to powers. The factors are names, calls and anything else which is not a product or a power of a constant.
+#[derive(Clone, Debug)]
+struct Term {
+    coefficient: Number,
+    factors: Vec<(Expr, f64)>,
+}
+
+/// Round away order-epsilon differences from whole powers, so that x^(1/3)^3 is x
+fn snap(power: f64) -> f64 {
+    if (power - power.round()).abs() < EPSILON { power.round() } else { power }
+}
+
+impl Term {
+    fn number(n: Number) -> Self {
+        Self { coefficient: n, factors: Vec::new() }
+    }
+
+    fn factor(base: Expr, power: f64) -> Self {
+        Self { coefficient: Number::new(1., Unit::one()), factors: vec![(base, power)] }
+    }
+
+    /// Multiply two products, combining the powers of like factors
+    fn mul(mut self, other: Term) -> Result<Self> {
+        self.coefficient = self.coefficient.mul(other.coefficient)?;
+        for (base, power) in other.factors {
+            match self.factors.iter_mut().find(|(b, _)| *b == base) {
+                Some((_, p)) => *p = snap(*p + power),
+                None => self.factors.push((base, power)),
+            }
+        }
+        self.factors.retain(|(_, p)| *p != 0.);
+        Ok(self)
+    }
+
+    /// Raise a product to a power, or None if its coefficient is negative and the power is not whole
+    fn powf(self, power: f64) -> Result<Option<Self>> {
+        if power.fract() != 0. && self.coefficient.values().iter().any(|q| *q < 0.) {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            coefficient: self.coefficient.expon(Number::new(power, Unit::one()))?,
+            factors: self.factors.into_iter().map(|(base, p)| (base, snap(p * power))).collect(),
+        }))
+    }
+
+    /// Whether two products differ only in their coefficient, so they can be added
+    fn like(&self, other: &Term) -> bool {
+        self.coefficient.u == other.coefficient.u
+            && self.factors.len() == other.factors.len()
+            && self.factors.iter().all(|factor| other.factors.contains(factor))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.coefficient.values().iter().all(|q| *q == 0.)
+    }
+
+    /// The product as an expression, with its sign separate: a fractional coefficient splits across the numerator and denominator, and negative powers go in the denominator
+    fn expr(&self) -> (Expr, bool) {
+        let mut numerator = Vec::new();
+        let mut denominator = Vec::new();
+        let mut negative = false;
+        match self.coefficient.value() {
+            Some(q) => {
+                negative = q < 0.;
+                let magnitude = q.abs();
+                match fraction(magnitude).filter(|_| self.coefficient.u.is_one()) {
+                    Some((num, denom)) => {
+                        if num != 1 || self.factors.is_empty() {
+                            numerator.push(number(num as f64));
+                        }
+                        if denom != 1 {
+                            denominator.push(number(denom as f64));
+                        }
+                    },
+                    None => numerator.push(Expr::Number(Number::new(magnitude, self.coefficient.u))),
+                }
+            },
+            None => numerator.push(Expr::Number(self.coefficient.clone())),
+        }
+        // Names come first, as in 2 x sin(x)
+        let mut factors: Vec<&(Expr, f64)> = self.factors.iter().collect();
+        factors.sort_by_key(|(base, _)| match base {
+            Expr::Name(_) => 0,
+            Expr::Call(..) => 1,
+            _ => 2,
+        });
+        for (base, power) in factors {
+            let (side, power) = if *power < 0. { (&mut denominator, -power) } else { (&mut numerator, *power) };
+            side.push(if power == 1. { base.clone() } else { binary(BinaryOp::Expon, base.clone(), number(power)) });
+        }
+        let product = |factors: Vec<Expr>| factors.into_iter().reduce(|a, b| binary(BinaryOp::Mul, a, b)).unwrap_or_else(|| number(1.));
+        let expr = if denominator.is_empty() {
+            product(numerator)
+        } else {
+            binary(BinaryOp::Div, product(numerator), product(denominator))
+        };
+        (expr, negative)
+    }
+}
+
+/// Add sums, collecting like terms
+fn add(mut a: Vec<Term>, b: Vec<Term>) -> Result<Vec<Term>> {
+    for term in b {
+        match a.iter_mut().find(|t| t.like(&term)) {
+            Some(t) => t.coefficient = t.coefficient.clone().add(term.coefficient)?,
+            None => a.push(term),
+        }
+    }
+    let zero = a.iter().find(|t| t.is_zero()).map(|t| Number::new(0., t.coefficient.u));
+    a.retain(|t| !t.is_zero());
+    if a.is_empty() {
+        a.push(Term::number(zero.unwrap_or(Number::new(0., Unit::one()))));
+    }
+    Ok(a)
+}
+
+fn negate(terms: Vec<Term>) -> Result<Vec<Term>> {
+    terms.into_iter().map(|t| Ok(Term { coefficient: t.coefficient.neg()?, factors: t.factors })).collect()
+}
+
+/// Multiply sums. A number multiplies each term of a sum, but other sums are kept whole as factors rather than expanded.
+fn product(a: Vec<Term>, b: Vec<Term>) -> Result<Vec<Term>> {
+    let plain = |terms: &[Term]| terms.len() == 1 && terms[0].factors.is_empty();
+    if let ([x], [y]) = (a.as_slice(), b.as_slice()) {
+        return Ok(vec![x.clone().mul(y.clone())?]);
+    }
+    if plain(&a) {
+        return b.into_iter().map(|t| a[0].clone().mul(t)).collect();
+    }
+    if plain(&b) {
+        return a.into_iter().map(|t| t.mul(b[0].clone())).collect();
+    }
+    product(vec![Term::factor(rebuild(&a), 1.)], vec![Term::factor(rebuild(&b), 1.)])
+}
+
+/// Raise a sum to a power. Only a single product is raised term by term.
+fn power(terms: Vec<Term>, p: f64) -> Result<Vec<Term>> {
+    if p == 0. {
+        return Ok(vec![Term::number(Number::new(1., Unit::one()))]);
+    }
+    if let [term] = terms.as_slice() && let Some(term) = term.clone().powf(p)? {
+        return Ok(vec![term]);
+    }
+    Ok(vec![Term::factor(rebuild(&terms), p)])
+}
+
+/// An expression as a sum of products
+fn terms(expr: &Expr) -> Result<Vec<Term>> {
+    use BinaryOp::*;
+    Ok(match expr {
+        Expr::Number(n) => vec![Term::number(n.clone())],
+        Expr::Neg(e) => negate(terms(e)?)?,
+        Expr::Binary(Add, a, b) => add(terms(a)?, terms(b)?)?,
+        Expr::Binary(Sub, a, b) => add(terms(a)?, negate(terms(b)?)?)?,
+        Expr::Binary(Mul, a, b) => product(terms(a)?, terms(b)?)?,
+        Expr::Binary(Div, a, b) => product(terms(a)?, power(terms(b)?, -1.)?)?,
+        Expr::Binary(Expon, a, b) => {
+            let exponent = terms(b)?;
+            match exponent.as_slice() {
+                [t] if t.factors.is_empty() && t.coefficient.u.is_one() && let Some(p) = t.coefficient.value() => power(terms(a)?, p)?,
+                _ => vec![Term::factor(binary(Expon, simplify(a)?, rebuild(&exponent)), 1.)],
+            }
+        },
+        Expr::Call(name, args) => vec![Term::factor(Expr::Call(name.clone(), args.iter().map(simplify).collect::<Result<_>>()?), 1.)],
+        Expr::List(items) => vec![Term::factor(Expr::List(items.iter().map(simplify).collect::<Result<_>>()?), 1.)],
+        Expr::Level(level, name) => vec![Term::factor(Expr::Level(Box::new(simplify(level)?), name.clone()), 1.)],
+        _ => vec![Term::factor(expr.clone(), 1.)],
+    })
+}
+
+/// A sum of products as an expression
+fn rebuild(terms: &[Term]) -> Expr {
+    let mut sum: Option<Expr> = None;
+    for term in terms {
+        let (expr, negative) = term.expr();
+        sum = Some(match (sum, negative) {
+            (None, false) => expr,
+            (None, true) => Expr::Neg(Box::new(expr)),
+            (Some(sum), false) => binary(BinaryOp::Add, sum, expr),
+            (Some(sum), true) => binary(BinaryOp::Sub, sum, expr),
+        });
+    }
+    sum.unwrap_or_else(|| number(0.))
+}
+
+/// Simplify an expression by writing it as a sum of products: numbers are collected into one coefficient per term, like powers combine as in x^2 x = x^3, and like terms are added
+pub fn simplify(expr: &Expr) -> Result<Expr> {
+    Ok(rebuild(&terms(expr)?))
+}
+
+/// Evaluate as much of an expression as possible, leaving the names which have no value as symbols. Names defined in terms of symbols are replaced by their definitions.
+pub fn partial(expr: &Expr, ctx: &Context) -> Result<Expr> {
+    partial_in(expr, ctx, &[])
+}
+
+/// partial, leaving the bound variables of the derivatives around expr as symbols even if names outside have values
+fn partial_in(expr: &Expr, ctx: &Context, bound: &[&str]) -> Result<Expr> {
+    let p = |e: &Expr| partial_in(e, ctx, bound);
+    let node = match expr {
+        Expr::Name(name) => {
+            if bound.contains(&name.as_str()) {
+                return Ok(expr.clone());
+            }
+            if let Some(n) = ctx.variable(name) {
+                return Ok(Expr::Number(n));
+            }
+            if let Some(definition) = ctx.symbol(name) {
+                return p(definition);
+            }
+            if ctx.lookup(name).is_none() && ctx.function(name).is_none() {
+                return Ok(expr.clone());
+            }
+            return Ok(Expr::Number(expr.eval(ctx)?));
+        },
+        Expr::Neg(e) => Expr::Neg(Box::new(p(e)?)),
+        Expr::Binary(op, a, b) => binary(*op, p(a)?, p(b)?),
+        Expr::Call(name, args) => {
+            if ctx.function(name).is_none() {
+                return Err(CalcError::UnknownName(name.clone()));
+            }
+            Expr::Call(name.clone(), args.iter().map(p).collect::<Result<_>>()?)
+        },
+        Expr::List(items) => Expr::List(items.iter().map(p).collect::<Result<_>>()?),
+        Expr::Level(level, name) => Expr::Level(Box::new(p(level)?), name.clone()),
+        Expr::Derivative(d) => match &d.at {
+            None => return p(&derivative(&expand(&d.expr, ctx), &d.variable)?),
+            // A derivative at a point which is still a symbol, as d/dx gives for functions without a known derivative
+            Some(at) => {
+                let inner = [bound, &[d.variable.as_str()]].concat();
+                Expr::Derivative(Box::new(Derivative {
+                    expr: partial_in(&d.expr, ctx, &inner)?,
+                    variable: d.variable.clone(),
+                    at: Some(p(at)?),
+                }))
+            },
+        },
+        _ => expr.clone(),
+    };
+    if free_names(&node).is_empty() {
+        return Ok(Expr::Number(node.eval(ctx)?));
+    }
+    Ok(node)
+}
+
+/// The expression with each stored symbol replaced by its definition, so that d/dx(y) after y = x^2 sees the x in y. Everything else is left as written.
+pub fn expand(expr: &Expr, ctx: &Context) -> Expr {
+    match expr {
+        Expr::Name(name) if ctx.variable(name).is_none() && let Some(definition) = ctx.symbol(name) => expand(definition, ctx),
+        Expr::Neg(e) => Expr::Neg(Box::new(expand(e, ctx))),
+        Expr::Binary(op, a, b) => binary(*op, expand(a, ctx), expand(b, ctx)),
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(|a| expand(a, ctx)).collect()),
+        Expr::List(items) => Expr::List(items.iter().map(|a| expand(a, ctx)).collect()),
+        Expr::Level(level, name) => Expr::Level(Box::new(expand(level, ctx)), name.clone()),
+        Expr::Derivative(d) if d.at.is_none() => Expr::Derivative(Box::new(Derivative {
+            expr: expand(&d.expr, ctx),
+            variable: d.variable.clone(),
+            at: None,
+        })),
+        _ => expr.clone(),
+    }
+}
+
+/// The names an expression leaves as symbols, in the order they appear
+pub fn free_names(expr: &Expr) -> Vec<String> {
+    let mut names = Vec::new();
+    collect_names(expr, &mut names);
+    names
+}
+
+fn collect_names(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Name(name) if !names.contains(name) => names.push(name.clone()),
+        Expr::Neg(e) | Expr::Level(e, _) => collect_names(e, names),
+        Expr::Binary(_, a, b) => {
+            collect_names(a, names);
+            collect_names(b, names);
+        },
+        Expr::Call(_, args) | Expr::List(args) => args.iter().for_each(|a| collect_names(a, names)),
+        // The variable of a derivative at a point is bound, so only the point can leave it free
+        Expr::Derivative(d) => {
+            let mut inner = Vec::new();
+            collect_names(&d.expr, &mut inner);
+            for name in inner {
+                if (d.at.is_none() || name != d.variable) && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            if let Some(at) = &d.at {
+                collect_names(at, names);
+            }
+        },
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{expr::Statement, parse::parse};
+
+    fn d(text: &str) -> Result<String> {
+        let Ok(Statement::Expr(expr)) = parse(text) else {
+            panic!("{} is not an expression", text);
+        };
+        derivative(&expr, "x").map(|e| e.to_string())
+    }
+
+    #[test]
+    fn differentiates_functions_in_closed_form() {
+        assert_eq!(d("abs(x^2 - 1)").unwrap(), "2 x sign(x^2 - 1)");
+        assert_eq!(d("floor(x) + ceil(x) + round(x) + sign(x)").unwrap(), "0");
+        assert_eq!(d("digamma(x)").unwrap(), "trigamma(x)");
+        assert_eq!(d("atanh(x)").unwrap(), "1 / (1 - x^2)");
+        assert_eq!(d("gammq(2, x)").unwrap(), "-x exp(-x) / gamma(2)");
+        assert_eq!(d("besselj(0, x)").unwrap(), "-besselj(1, x)");
+        assert_eq!(d("besseli(1, x)").unwrap(), "besseli(1, x) / x + besseli(2, x)");
+        assert_eq!(d("beta(x, 2)").unwrap(), "beta(x, 2) (digamma(x) - digamma(x + 2))");
+    }
+
+    #[test]
+    fn refuses_derivatives_with_no_closed_form() {
+        let message = |text: &str| d(text).unwrap_err().to_string();
+        assert_eq!(message("gammp(x, 2)"), "gammp has no derivative in closed form with respect to its argument a. Use diff(expr, x, at) at a point");
+        assert_eq!(message("besselk(x, 2)"), "besselk has no derivative in closed form with respect to its argument nu. Use diff(expr, x, at) at a point");
+        assert_eq!(message("trigamma(x)"), "trigamma has no derivative in closed form. Use diff(expr, x, at) at a point");
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{context::Context, error::{CalcError, Result}, expr::Expr, format::Format, number::Number, unit::Unit};
+
+/// A column of a table: a name, the unit of its values, and the values in that unit
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub unit: Unit,
+    pub values: Vec<f64>,
+}
+
+impl Column {
+    /// The name with the unit in brackets, unless there is no unit
+    pub fn header(&self, format: &Format) -> String {
+        if self.unit.is_one() {
+            self.name.clone()
+        } else {
+            format!("{} [{}]", self.name, self.unit.format(format).trim())
+        }
+    }
+}
+
+/// Columns of values which line up row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub columns: Vec<Column>,
+}
+
+impl Table {
+    fn rows(&self) -> usize {
+        self.columns.iter().map(|c| c.values.len()).max().unwrap_or(0)
+    }
+
+    /// The table as text, with the values of each column aligned on the right
+    pub fn format(&self, format: &Format) -> String {
+        let mut cells: Vec<Vec<String>> = self.columns.iter()
+            .map(|c| {
+                let mut cells = vec![c.header(format)];
+                cells.extend(c.values.iter().map(|q| format.apply(*q)));
+                cells
+            })
+            .collect();
+        let widths: Vec<usize> = cells.iter().map(|c| c.iter().map(|s| s.chars().count()).max().unwrap_or(0)).collect();
+        for column in cells.iter_mut() {
+            column.resize(self.rows() + 1, String::new());
+        }
+        (0..=self.rows())
+            .map(|row| cells.iter().zip(&widths)
+                .map(|(column, width)| format!("{:>width$}", column[row], width = width))
+                .collect::<Vec<_>>()
+                .join("  "))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The table as comma-separated values, with a header row
+    pub fn csv(&self, format: &Format) -> String {
+        let mut lines = vec![self.columns.iter().map(|c| csv_field(&c.header(format))).collect::<Vec<_>>().join(",")];
+        for row in 0..self.rows() {
+            lines.push(self.columns.iter()
+                .map(|c| c.values.get(row).map_or(String::new(), |q| format.apply(*q)))
+                .collect::<Vec<_>>()
+                .join(","));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Quote a CSV field if it contains a comma or a quote
+fn csv_field(text: &str) -> String {
+    if text.contains(',') || text.contains('"') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// The number of values a sweep takes if no count is given
+const DEFAULT_COUNT: usize = 10;
+
+/// How the values of a sweep are spaced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spacing {
+    Linear,
+    Log,
+}
+
+/// `x, a .. b, count, log`: a variable and the values it takes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sweep {
+    pub variable: String,
+    pub from: Expr,
+    pub to: Expr,
+    /// The number of values, or the default if None
+    pub count: Option<Expr>,
+    pub spacing: Spacing,
+}
+
+impl Sweep {
+    /// The values the variable takes, from the start to the end of the range inclusive, and their unit
+    pub fn points(&self, ctx: &Context) -> Result<(Vec<f64>, Unit)> {
+        let from = self.from.eval(ctx)?.quantity("The start of a range")?;
+        let to = self.to.eval(ctx)?.quantity("The end of a range")?;
+        let (Some(a), Some(b)) = (from.value(), to.value()) else {
+            return Err(CalcError::Domain("The ends of a range must be single numbers".to_owned()));
+        };
+        if from.u != to.u {
+            return Err(CalcError::Unit(format!("The ends of the range {} .. {} have different units", from, to)));
+        }
+        let count = match &self.count {
+            None => DEFAULT_COUNT,
+            Some(count) => {
+                let count = count.eval(ctx)?;
+                match count.value() {
+                    Some(n) if count.u.is_one() && n >= 2. && n.fract() == 0. => n as usize,
+                    _ => return Err(CalcError::Domain(format!("The number of values must be a whole number of at least 2, not {}", count))),
+                }
+            },
+        };
+        let step = |i: usize| i as f64 / (count - 1) as f64;
+        let mut points: Vec<f64> = match self.spacing {
+            Spacing::Linear => (0..count).map(|i| a + (b - a) * step(i)).collect(),
+            Spacing::Log => {
+                if a * b <= 0. {
+                    return Err(CalcError::Domain(format!("A logarithmic range cannot include zero, but {} .. {} does", from, to)));
+                }
+                (0..count).map(|i| a * (b / a).powf(step(i))).collect()
+            },
+        };
+        // Rounding should not move the end of the range
+        points[count - 1] = b;
+        Ok((points, from.u))
+    }
+
+    /// A table of the variable and the value of an expression, labelled by its text, at each value of the sweep. The expression must have a single value with the same unit everywhere.
+    /// Points outside the domain of the expression or where it overflows, as where ln(x) has x <= 0, are left as gaps.
+    pub fn tabulate(&self, ctx: &Context, expr: &Expr, label: &str) -> Result<Table> {
+        let (points, unit) = self.points(ctx)?;
+        let mut scope = ctx.clone();
+        let mut values = Vec::new();
+        let mut value_unit = None;
+        for x in &points {
+            scope.set_variable(&self.variable, Number::new(*x, unit));
+            let n = match expr.eval(&scope) {
+                Err(CalcError::Domain(_) | CalcError::Overflow(_)) => {
+                    values.push(f64::NAN);
+                    continue;
+                },
+                n => n?.quantity("A value in a table")?,
+            };
+            let Some(q) = n.value() else {
+                return Err(CalcError::Domain("The expression must have a single value at each point, not an array".to_owned()));
+            };
+            if value_unit.is_some_and(|u| u != n.u) {
+                return Err(CalcError::Unit(format!("The expression changes units within the range of {}", self.variable)));
+            }
+            value_unit = Some(n.u);
+            values.push(q);
+        }
+        Ok(Table {
+            columns: vec![
+                Column { name: self.variable.clone(), unit, values: points },
+                Column { name: label.to_owned(), unit: value_unit.unwrap_or(Unit::one()), values },
+            ],
+        })
+    }
+}
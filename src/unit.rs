@@ -1,5 +1,30 @@
 use std::{fmt::Display, ops::{Add, Mul, Sub}};
-use crate::util::{round_eps, EPSILON};
+use crate::{format::Format, util::{round_eps, EPSILON}};
+
+/// Names of common dimensions and their powers of cm, g and s
+const DIMENSIONS: &[(&str, [f64; 3])] = &[
+    ("dimensionless", [0., 0., 0.]),
+    ("length", [1., 0., 0.]),
+    ("area", [2., 0., 0.]),
+    ("volume", [3., 0., 0.]),
+    ("number density", [-3., 0., 0.]),
+    ("mass", [0., 1., 0.]),
+    ("density", [-3., 1., 0.]),
+    ("time", [0., 0., 1.]),
+    ("frequency", [0., 0., -1.]),
+    ("velocity", [1., 0., -1.]),
+    ("acceleration", [1., 0., -2.]),
+    ("momentum", [1., 1., -1.]),
+    ("force", [1., 1., -2.]),
+    ("energy", [2., 1., -2.]),
+    ("power", [2., 1., -3.]),
+    ("action", [2., 1., -1.]),
+    ("pressure", [-1., 1., -2.]),
+    ("flux", [0., 1., -3.]),
+    ("spectral flux density", [0., 1., -2.]),
+    ("charge", [1.5, 0.5, -1.]),
+    ("magnetic field", [-0.5, 0.5, -1.]),
+];
 
 #[derive(Clone, Copy, Debug)]
 pub struct Unit {
@@ -25,6 +50,42 @@ impl Unit {
         }
     }
 
+    /// The powers of cm, g and s
+    pub fn exponents(&self) -> [f64; 3] {
+        [self.cm, self.g, self.s]
+    }
+
+    pub fn cm(&self) -> f64 {
+        self.cm
+    }
+
+    pub fn g(&self) -> f64 {
+        self.g
+    }
+
+    pub fn s(&self) -> f64 {
+        self.s
+    }
+
+    /// The unit of a common dimension, such as energy, given its name
+    pub fn dimension(name: &str) -> Option<Self> {
+        DIMENSIONS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, exponents)| Unit::new(*exponents))
+    }
+
+    /// The names of the common dimensions
+    pub fn dimension_names() -> impl Iterator<Item = &'static str> {
+        DIMENSIONS.iter().map(|(name, _)| *name)
+    }
+
+    /// The name of this dimension, if it is a common one
+    pub fn dimension_name(&self) -> Option<&'static str> {
+        DIMENSIONS.iter()
+            .find(|(_, exponents)| Unit::new(*exponents) == *self)
+            .map(|(name, _)| *name)
+    }
+
     pub fn is_one(&self) -> bool {
         self.cm.abs() < EPSILON && self.g.abs() < EPSILON && self.s.abs() < EPSILON
     }
@@ -66,37 +127,38 @@ impl PartialEq for Unit {
     }
 }
 
-impl Display for Unit {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let cm_power = round_eps(self.cm);
-        let g_power = round_eps(self.g);
-        let s_power = round_eps(self.s);
+impl Unit {
+    /// Print the unit, formatting irrational exponents with the given format
+    pub fn format(&self, format: &Format) -> String {
+        let cm_power = round_eps(self.cm, format);
+        let g_power = round_eps(self.g, format);
+        let s_power = round_eps(self.s, format);
         let mut out = "".to_owned();
 
         if cm_power == "1" {
             out = format!("{} cm", out)
-        } else if cm_power == "0" {
-            out = format!("{}", out)
-        } else {
+        } else if cm_power != "0" {
             out = format!("{} cm^{}", out, cm_power)
         }
 
         if g_power == "1" {
             out = format!("{} g", out)
-        } else if g_power == "0" {
-            out = format!("{}", out)
-        } else {
+        } else if g_power != "0" {
             out = format!("{} g^{}", out, g_power)
         }
 
         if s_power == "1" {
             out = format!("{} s", out)
-        } else if s_power == "0" {
-            out = format!("{}", out)
-        } else {
+        } else if s_power != "0" {
             out = format!("{} s^{}", out, s_power)
         }
 
-        write!(f, "{}", out)
+        out
     }
-}
\ No newline at end of file
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(&Format::default()))
+    }
+}
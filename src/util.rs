@@ -1,18 +1,31 @@
+use crate::format::Format;
+
 pub const EPSILON: f64 = 1e-10;
-/// Round away order-epsilon differences between a floating point number and a simple rational number
-pub fn round_eps(n: f64) -> String {
+/// Round away order-epsilon differences between a floating point number and a simple rational number. Numbers which are not simple rationals are printed with the given format.
+pub fn round_eps(n: f64, format: &Format) -> String {
+    match fraction(n) {
+        Some((num, 1)) => format!("{}", num),
+        Some((num, denom)) => format!("{}/{}", num, denom),
+        None => format.apply(n),
+    }
+}
+
+/// The numerator and denominator of a simple rational number within epsilon of n, with a denominator of at most 8
+pub fn fraction(n: f64) -> Option<(i32, i32)> {
+    if !n.is_finite() || n.abs() >= i32::MAX as f64 {
+        return None;
+    }
     let fractional_part = n - n.floor();
     let integer_part = n.floor() as i32;
     if fractional_part.abs() < EPSILON {
-        return format!("{}", integer_part);
+        return Some((integer_part, 1));
     }
     for denom in 0..=8 {
         for num in 0..denom {
             if (fractional_part - (num as f64 / denom as f64)).abs() < EPSILON {
-                let num_added = num + denom * integer_part;
-                return format!("{}/{}", num_added, denom);
+                return Some((num + denom * integer_part, denom));
             }
         }
     }
-    return format!("{}", n);
-}
\ No newline at end of file
+    None
+}